@@ -1,5 +1,7 @@
 WIDTH 1024
 HEIGHT 1024
+MEMORY 16
+PALETTE 0xffffff 0xff8000
 
 DRW
 
@@ -38,6 +40,214 @@ INC S
 DEC A
 DEC S
 
+; MIN Rx n
+; MIN Rx Ry
+MIN A 1
+MIN S A
+
+; MAX Rx n
+; MAX Rx Ry
+MAX A 1
+MAX S A
+
+; ABS Rx
+ABS A
+ABS S
+
+; PUSH Rx
+; POP Rx
+PUSH A
+PUSH S
+POP S
+POP A
+
+; LDI Rx Ri
+; STI Ri Rx
+STO B 8
+LDI A B
+STI B A
+
+; LOAD Ra Rx
+; SAVE Ra Rx
+STO B 15
+SAVE B A
+LOAD B S
+
+; FILL
+FILL
+
+; HOME
+HOME
+
+; PLOT
+PLOT
+
+; DBG
+DBG
+
+; COLI n
+; COLI Ry
+COLI 1
+STO B 0
+COLI B
+
+; PW n
+; PW Ry
+PW 3
+PLOT
+STO B 1
+PW B
+
+; TIME Rx
+TIME A
+TIME S
+
+; SKZ Rx
+; SKNZ Rx
+SKZ B
+INC B
+SKNZ S
+INC S
+
+; DASH on off
+; DASH on Ry
+; DASH Rx off
+; DASH Rx Ry
+DASH 4 2
+FWD
+FWD
+DASH 4 B
+DASH B 2
+DASH B B
+FWD
+DASH 0 0
+
+; HEADING Rx Ry
+STO S 3
+STO T 4
+HEADING S T
+FWD
+
+; RGB Rr Rg Rb
+STO B 255
+STO C 128
+STO D 300
+RGB B C D
+FWD
+
+; SEL n
+; SEL Ry
+SEL 1
+DRW
+ADD A 90
+FWD
+FWD
+STO B 0
+SEL B
+FWD
+
+; JMPR Rx
+LEA jmpr: B
+JMPR B
+jmpr:
+
+; MIRROR n reflect
+; MIRROR n Ry
+; MIRROR Rx reflect
+; MIRROR Rx Ry
+STO B 3
+STO C 1
+MIRROR 4 1
+FWD
+MIRROR 6 C
+FWD
+MIRROR B 0
+FWD
+MIRROR B C
+FWD
+MIRROR 0 0
+
+; WAIT n
+; WAIT Ry
+WAIT 0
+STO B 0
+WAIT B
+
+; CVT Rx Ry
+STO S 2.5
+CVT B S
+CVT T B
+CVT S S
+
+; POLY n len
+; POLY n Ry
+; POLY Rx len
+; POLY Rx Ry
+STO B 5
+POLY 3 10
+POLY 4 B
+POLY B 10
+POLY B B
+; SPIRAL n growth
+; SPIRAL n Ry
+; SPIRAL Rx growth
+; SPIRAL Rx Ry
+STO B 2
+SPIRAL 2 5
+SPIRAL 2 B
+SPIRAL B 5
+SPIRAL B B
+
+; SAMPLE Rx
+SAMPLE B
+SAMPLE S
+
+; MOVE Rx Ry
+STO S 3.5
+STO B 2
+MOVE S B
+MOVE B S
+
+; CMP Rx n
+; CMP Rx Ry
+; JF EQ label:
+CMP A 0
+CMP S A
+JF NE cmp1:
+JF EQ cmp1:
+cmp1:
+
+; GRAD n
+; GRAD Ry
+GRAD 100
+GRAD A
+GRAD 0
+
+; AUNIT DEG
+
+; ARC r n
+; ARC r Ry
+; ARC Rx n
+; ARC Rx Ry
+ARC 10 90
+ARC 10 A
+ARC A 90
+ARC A A
+; AUNIT RAD
+; AUNIT TURN
+AUNIT RAD
+AUNIT TURN
+AUNIT DEG
+
+; ARC r n
+; ARC r Ry
+; ARC Rx n
+; ARC Rx Ry
+ARC 10 90
+ARC 10 A
+ARC A 90
+ARC A A
+
 STO A 1
 STO S 0
 
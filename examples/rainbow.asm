@@ -0,0 +1,28 @@
+WIDTH 1024
+HEIGHT 1024
+
+DRW
+
+; Cycle through the spectrum every 2000 cycles
+GRAD 2000
+
+STO E 100
+STO D 1
+
+mark_1:
+  STO C D
+
+  mark_2:
+    DEC C
+    FWD
+    JNZ C mark_2:
+
+  STO A D
+  MUL A 15
+
+  INC D
+  DEC E
+
+JNZ E mark_1:
+
+HLT
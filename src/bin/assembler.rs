@@ -1,102 +1,327 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
-use std::fs::File;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
 use std::io::{stdin, Read, Write};
+use std::ops::{Index, IndexMut};
+use std::path::{Path, PathBuf};
 
-use drawer::Opcode;
+use drawer::instruction::{
+    decode, write_varint, Endianness, Instruction, FLAG_SOURCE, FLAG_VARINT, FLAG_WIDE_ADDRESSES,
+    FLOAT_IMMEDIATE, MAX_DIMENSION, PALETTE_SIZE, VERSION,
+};
+use drawer::{register_from_str, Opcode, OperandKind};
 
-fn read_stdin() -> String {
-    let mut buffer = String::new();
-    stdin()
-        .read_to_string(&mut buffer)
-        .expect("unable to read from STDIN");
-    buffer
-}
-
-fn parse_register(input: Option<&str>) -> Result<u8, String> {
-    if let Some(input) = input {
-        match input {
-            "A" => Ok(0x0),
-            "B" => Ok(0x1),
-            "C" => Ok(0x2),
-            "D" => Ok(0x3),
-            "E" => Ok(0x4),
-            "F" => Ok(0x5),
-            "G" => Ok(0x6),
-            "H" => Ok(0x7),
-            "S" => Ok(0x8),
-            "T" => Ok(0x9),
-            "U" => Ok(0xa),
-            "V" => Ok(0xb),
-            "W" => Ok(0xc),
-            "X" => Ok(0xd),
-            "Y" => Ok(0xe),
-            "Z" => Ok(0xf),
-            _ => Err(format!("not a register: {}", input)),
+/// A problem with the source. These are collected while assembling so they can all be reported
+/// at once.
+#[derive(Debug, PartialEq)]
+enum AssembleError {
+    /// An invalid statement, on a line numbered from 1
+    Line {
+        line: usize,
+        message: String,
+    },
+    MissingWidth,
+    MissingHeight,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssembleError::Line { line, message } => write!(f, "line {}: {}", line, message),
+            AssembleError::MissingWidth => write!(f, "missing WIDTH"),
+            AssembleError::MissingHeight => write!(f, "missing HEIGHT"),
+        }
+    }
+}
+
+impl Error for AssembleError {}
+
+/// Assembled bytes, with multi-byte values written in the program's byte order.
+struct Output {
+    bytes: Vec<u8>,
+    endianness: Endianness,
+    /// Write immediate values as varints
+    varint: bool,
+    /// Write addresses as four bytes rather than two
+    wide_addresses: bool,
+    /// Instruction indices loaded with `LEA`, which `JMPR` may jump to
+    indirect_targets: Vec<usize>,
+}
+
+impl Output {
+    fn push(&mut self, byte: u8) {
+        self.bytes.push(byte);
+    }
+
+    fn push_u16(&mut self, value: u16) {
+        let bytes = self.endianness.u16_to_bytes(value);
+        self.bytes.extend_from_slice(&bytes);
+    }
+
+    /// Push an immediate value, as a varint if the program uses them.
+    fn push_immediate(&mut self, value: u16) {
+        if self.varint {
+            write_varint(&mut self.bytes, value);
+        } else {
+            self.push_u16(value);
         }
+    }
+
+    fn push_f64(&mut self, value: f64) {
+        let bytes = self.endianness.f64_to_bytes(value);
+        self.bytes.extend_from_slice(&bytes);
+    }
+
+    fn push_u32(&mut self, value: u32) {
+        let bytes = self.endianness.u32_to_bytes(value);
+        self.bytes.extend_from_slice(&bytes);
+    }
+
+    fn len(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+impl Index<usize> for Output {
+    type Output = u8;
+
+    fn index(&self, index: usize) -> &u8 {
+        &self.bytes[index]
+    }
+}
+
+impl IndexMut<usize> for Output {
+    fn index_mut(&mut self, index: usize) -> &mut u8 {
+        &mut self.bytes[index]
+    }
+}
+
+fn read_stdin() -> std::io::Result<String> {
+    let mut buffer = String::new();
+    stdin().read_to_string(&mut buffer)?;
+    Ok(buffer)
+}
+
+fn parse_register(input: Option<&str>, aliases: &Aliases) -> Result<u8, String> {
+    let input = input.ok_or("missing register")?;
+    register_from_str(aliases.resolve(input)).map_err(|e| e.to_string())
+}
+
+fn parse_u16(input: Option<&str>, constants: &Constants) -> Result<u16, String> {
+    constants.eval(input.ok_or("missing value")?)
+}
+
+/// Parse a `0xRRGGBB` or decimal color.
+fn parse_color(input: Option<&str>) -> Result<u32, String> {
+    let input = input.ok_or("missing color")?;
+    let color = match input.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => input.parse(),
+    };
+    match color {
+        Ok(color) if color <= 0xff_ffff => Ok(color),
+        _ => Err(format!("not a color: {}", input)),
+    }
+}
+
+/// A float literal, which has a decimal point unlike integer immediates, e.g. `0.5` or `-2.`.
+fn parse_float(input: Option<&str>) -> Option<f64> {
+    input
+        .filter(|input| input.contains('.'))
+        .and_then(|input| input.parse().ok())
+}
+
+/// Add a register or immediate value operand, returning whether it was flagged as a register.
+/// Float immediates are flagged too.
+fn add_value(
+    buffer: &mut Output,
+    constants: &Constants,
+    aliases: &Aliases,
+    operand: Option<&str>,
+) -> Result<bool, String> {
+    if let Ok(register) = parse_register(operand, aliases) {
+        buffer.push(register);
+        Ok(true)
+    } else if let Some(value) = parse_float(operand) {
+        buffer.push(FLOAT_IMMEDIATE);
+        buffer.push_f64(value);
+        Ok(true)
     } else {
-        Err("missing register".to_string())
+        let value = parse_u16(operand, constants)?;
+        buffer.push_immediate(value);
+        Ok(false)
     }
 }
 
-fn parse_u16(input: Option<&str>) -> u16 {
-    input.expect("missing value").parse().expect("not a u16")
+fn parse_angle_unit(input: Option<&str>) -> Result<u8, String> {
+    match input {
+        Some("DEG") => Ok(0x0),
+        Some("RAD") => Ok(0x1),
+        Some("TURN") => Ok(0x2),
+        Some(unit) => Err(format!("not an angle unit: {}", unit)),
+        None => Err("missing angle unit".to_string()),
+    }
 }
 
-fn add_instruction_0(buffer: &mut Vec<u8>, opcode: Opcode) {
-    buffer.push(opcode as u8);
+fn parse_condition(input: Option<&str>) -> Result<u8, String> {
+    match input {
+        Some("EQ") => Ok(0x0),
+        Some("NE") => Ok(0x1),
+        Some("GT") => Ok(0x2),
+        Some("LT") => Ok(0x3),
+        Some(condition) => Err(format!("not a condition: {}", condition)),
+        None => Err("missing condition".to_string()),
+    }
 }
 
-fn add_instruction_1(buffer: &mut Vec<u8>, opcode: Opcode, operand_1: Option<&str>) {
+/// Add `opcode` followed by the operands its [`OperandKind`]s call for.
+fn add_instruction<'a>(
+    buffer: &mut Output,
+    opcode: Opcode,
+    labels: &Labels,
+    constants: &Constants,
+    aliases: &Aliases,
+    scope: Option<&str>,
+    operands: &mut impl Iterator<Item = &'a str>,
+) -> Result<(), String> {
+    let kinds = opcode.operand_kinds();
+    let index = buffer.len();
     buffer.push(opcode as u8);
-    let register = parse_register(operand_1).unwrap();
+
+    // The high bit marks the last value as a register, the next bit the value before it
+    let mut values = kinds
+        .iter()
+        .filter(|&&kind| kind == OperandKind::Value)
+        .count();
+    for kind in kinds {
+        let operand = operands.next();
+        match kind {
+            OperandKind::Register => buffer.push(parse_register(operand, aliases)?),
+            OperandKind::Value => {
+                values -= 1;
+                if add_value(buffer, constants, aliases, operand)? {
+                    buffer[index] |= if values == 0 { 0x80 } else { 0x40 };
+                }
+            }
+            OperandKind::Address => add_label(buffer, labels, scope, operand)?,
+            OperandKind::AngleUnit => buffer.push(parse_angle_unit(operand)?),
+            OperandKind::Condition => buffer.push(parse_condition(operand)?),
+        }
+    }
+    Ok(())
+}
+
+/// Add `LEA label: Rx` as a `STO` of the label's instruction index, for `JMPR` to jump to.
+fn add_lea<'a>(
+    buffer: &mut Output,
+    labels: &Labels,
+    aliases: &Aliases,
+    scope: Option<&str>,
+    operands: &mut impl Iterator<Item = &'a str>,
+) -> Result<(), String> {
+    let label = operands.next();
+    // Registers are 16 bits, so JMPR can't reach as far as a wide address
+    let addr = u16::try_from(labels.get(scope, label)?)
+        .map_err(|_| format!("label too far for a register {}", label.unwrap_or_default()))?;
+    let register = parse_register(operands.next(), aliases)?;
+    buffer.push(Opcode::STO as u8);
     buffer.push(register);
+    buffer.push_immediate(addr);
+    buffer.indirect_targets.push(usize::from(addr));
+    Ok(())
 }
 
-fn add_instruction_2(
-    buffer: &mut Vec<u8>,
-    opcode: Opcode,
-    operand_1: Option<&str>,
-    operand_2: Option<&str>,
-) {
-    let r1 = parse_register(operand_1).unwrap();
-    if let Ok(r2) = parse_register(operand_2) {
-        buffer.push(opcode as u8 | 0x80);
-        buffer.push(r1);
-        buffer.push(r2);
+fn add_label(
+    buffer: &mut Output,
+    labels: &Labels,
+    scope: Option<&str>,
+    label: Option<&str>,
+) -> Result<(), String> {
+    let addr = labels.get(scope, label)?;
+    if buffer.wide_addresses {
+        buffer.push_u32(addr);
     } else {
-        let value = parse_u16(operand_2);
-        buffer.push(opcode as u8);
-        buffer.push(r1);
-        buffer.extend_from_slice(&value.to_le_bytes());
+        // Only programs with labels past u16::MAX use wide addresses
+        buffer.push_u16(addr as u16);
     }
+    Ok(())
 }
 
-fn add_label(buffer: &mut Vec<u8>, labels: &Labels, label: Option<&str>) {
-    let addr = labels.get(label).unwrap();
-    buffer.extend_from_slice(&addr.to_le_bytes());
+/// Words other than mnemonics and labels that start a statement.
+const DIRECTIVES: [&str; 8] = [
+    "WIDTH", "HEIGHT", "MEMORY", "BG", "PALETTE", "CONST", "ALIAS", "LEA",
+];
+
+/// Whether `text` after a `;` is another statement rather than a comment, because it starts
+/// with a mnemonic, directive or label.
+fn is_statement(text: &str) -> bool {
+    tokens(text).next().is_some_and(|word| {
+        word.ends_with(':') || DIRECTIVES.contains(&word) || Opcode::try_from(word).is_ok()
+    })
+}
+
+/// Split a line into its `;` separated statements, e.g. `INC B; FWD`. A `#` starts a comment
+/// that runs to the end of the line, as does a `;` followed by anything but a statement, e.g.
+/// `STO A 1 ; set A`, and lines starting with `;` are comments too.
+fn statements(line: &str) -> impl Iterator<Item = &str> {
+    let line = line.split('#').next().unwrap_or_default().trim();
+    let code = if line.starts_with(';') { "" } else { line };
+
+    code.split(';')
+        .map(str::trim)
+        .enumerate()
+        .take_while(|&(i, s)| i == 0 || s.is_empty() || is_statement(s))
+        .map(|(_, s)| s)
+        .filter(|s| !s.is_empty())
+}
+
+/// Split a statement into its mnemonic and operands, which can be separated by whitespace,
+/// commas or both, e.g. `STO A, 5`.
+fn tokens(statement: &str) -> impl Iterator<Item = &str> {
+    statement
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|s| !s.is_empty())
+}
+
+/// Labels starting with a `.` are local to the closest preceding non-local label, so the same
+/// local name can be re-used under different parents.
+fn is_local(label: &str) -> bool {
+    label.starts_with('.')
 }
 
 struct Labels<'a> {
-    inner: HashMap<&'a str, u16>,
+    inner: HashMap<(Option<&'a str>, &'a str), u32>,
 }
 
 impl<'a> Labels<'a> {
-    fn new(input: &'a str) -> Self {
+    fn new(lines: &[(usize, &'a str)], errors: &mut Vec<AssembleError>) -> Self {
         let mut labels = HashMap::new();
-        let mut instruction_count = 0;
+        let mut instruction_count: u32 = 0;
+        let mut scope = None;
 
-        for line in input.lines() {
-            let mut parts = line.trim().split_whitespace();
+        for &(number, line) in lines {
+            for statement in statements(line) {
+                let mut parts = tokens(statement);
 
-            if let Some(prefix) = parts.next() {
-                if let Ok(_) = Opcode::try_from(prefix) {
-                    instruction_count += 1;
-                } else if prefix.ends_with(':') {
-                    if labels.contains_key(prefix) {
-                        panic!("re-used label: {}", prefix);
-                    } else {
-                        labels.insert(prefix, instruction_count);
+                if let Some(prefix) = parts.next() {
+                    if prefix == "LEA" || Opcode::try_from(prefix).is_ok() {
+                        instruction_count += 1;
+                    } else if prefix.ends_with(':') {
+                        if !is_local(prefix) {
+                            scope = Some(prefix);
+                        }
+
+                        if labels
+                            .insert(Self::key(scope, prefix), instruction_count)
+                            .is_some()
+                        {
+                            errors.push(AssembleError::Line {
+                                line: number + 1,
+                                message: format!("re-used label: {}", prefix),
+                            });
+                        }
                     }
                 }
             }
@@ -105,108 +330,672 @@ impl<'a> Labels<'a> {
         Labels { inner: labels }
     }
 
-    fn get(&self, label: Option<&str>) -> Result<u16, String> {
+    fn key<'b>(scope: Option<&'b str>, label: &'b str) -> (Option<&'b str>, &'b str) {
+        if is_local(label) {
+            (scope, label)
+        } else {
+            (None, label)
+        }
+    }
+
+    /// Whether some label is past where a two byte address can reach.
+    fn need_wide_addresses(&self) -> bool {
+        self.inner.values().any(|&addr| addr > u32::from(u16::MAX))
+    }
+
+    fn get(&self, scope: Option<&str>, label: Option<&str>) -> Result<u32, String> {
         if let Some(label) = label {
             self.inner
-                .get(label)
+                .get(&Self::key(scope, label))
+                .copied()
                 .ok_or_else(|| format!("label not found {}", label))
-                .map(|n| *n)
         } else {
             Err("missing label".to_string())
         }
     }
 }
 
-fn main() {
-    let input = read_stdin();
+/// Named values defined with `CONST name expr`, usable anywhere an immediate value is.
+struct Constants<'a> {
+    inner: HashMap<&'a str, u16>,
+}
 
-    let labels = Labels::new(&input);
+impl<'a> Constants<'a> {
+    fn new(lines: &[(usize, &'a str)], errors: &mut Vec<AssembleError>) -> Self {
+        let mut constants = Constants {
+            inner: HashMap::new(),
+        };
 
-    // Find width and height
-    let mut width = None;
-    let mut height = None;
+        for &(number, line) in lines {
+            for statement in statements(line) {
+                let mut parts = tokens(statement);
+
+                if parts.next() != Some("CONST") {
+                    continue;
+                }
+
+                if let Err(e) = constants.define(parts.next(), parts.next(), parts.next()) {
+                    errors.push(AssembleError::Line {
+                        line: number + 1,
+                        message: e,
+                    });
+                }
+            }
+        }
+
+        constants
+    }
+
+    fn define(
+        &mut self,
+        name: Option<&'a str>,
+        expr: Option<&str>,
+        extra: Option<&str>,
+    ) -> Result<(), String> {
+        let name = name.ok_or("missing constant name")?;
+        if register_from_str(name).is_ok() || name.parse::<u16>().is_ok() {
+            return Err(format!("invalid constant name: {}", name));
+        }
 
-    for line in input.lines() {
-        let mut parts = line.trim().split_whitespace();
+        // Constants can refer to the ones defined before them
+        let value = self.eval(expr.ok_or("missing value")?)?;
 
-        if width.is_some() && height.is_some() {
-            break;
+        if let Some(extra) = extra {
+            return Err(format!("unexpected operand: {}", extra));
         }
 
-        if let Some(prefix) = parts.next() {
-            match prefix {
-                "WIDTH" => {
-                    width = Some(parse_u16(parts.next()));
+        if self.inner.insert(name, value).is_some() {
+            return Err(format!("re-used constant: {}", name));
+        }
+        Ok(())
+    }
+
+    /// Evaluate a literal, constant, or an expression combining them with `+`, `-` and `*`, e.g.
+    /// `SIZE*2+1`. Expressions can't contain whitespace.
+    fn eval(&self, expr: &str) -> Result<u16, String> {
+        if let Ok(value) = expr.parse() {
+            return Ok(value);
+        }
+
+        let mut total = 0i64;
+        let mut sign = 1;
+        let mut start = 0;
+
+        let end = std::iter::once((expr.len(), '+'));
+        for (i, c) in expr.char_indices().chain(end) {
+            if c == '+' || c == '-' {
+                total += sign * self.eval_product(&expr[start..i])?;
+                sign = if c == '+' { 1 } else { -1 };
+                start = i + 1;
+            }
+        }
+
+        u16::try_from(total).map_err(|_| format!("out of range for a u16: {}", expr))
+    }
+
+    fn eval_product(&self, expr: &str) -> Result<i64, String> {
+        expr.split('*').try_fold(1i64, |product, atom| {
+            let value = match atom.parse::<u16>() {
+                Ok(value) => value,
+                Err(_) => *self
+                    .inner
+                    .get(atom)
+                    .ok_or_else(|| format!("not a u16 or constant: {}", atom))?,
+            };
+            product
+                .checked_mul(value as i64)
+                .ok_or_else(|| format!("overflow in: {}", expr))
+        })
+    }
+}
+
+/// Names for registers defined with `ALIAS name register`, usable anywhere a register is.
+struct Aliases<'a> {
+    inner: HashMap<&'a str, &'a str>,
+}
+
+impl<'a> Aliases<'a> {
+    fn new(
+        lines: &[(usize, &'a str)],
+        constants: &Constants,
+        errors: &mut Vec<AssembleError>,
+    ) -> Self {
+        let mut aliases = Aliases {
+            inner: HashMap::new(),
+        };
+
+        for &(number, line) in lines {
+            for statement in statements(line) {
+                let mut parts = tokens(statement);
+
+                if parts.next() != Some("ALIAS") {
+                    continue;
                 }
-                "HEIGHT" => {
-                    height = Some(parse_u16(parts.next()));
+
+                if let Err(e) = aliases.define(constants, parts.next(), parts.next(), parts.next())
+                {
+                    errors.push(AssembleError::Line {
+                        line: number + 1,
+                        message: e,
+                    });
                 }
-                _ => continue,
             }
         }
+
+        aliases
     }
 
-    let mut out = Vec::new();
+    fn define(
+        &mut self,
+        constants: &Constants,
+        name: Option<&'a str>,
+        register: Option<&'a str>,
+        extra: Option<&str>,
+    ) -> Result<(), String> {
+        let name = name.ok_or("missing alias name")?;
+        // Constants are tried after registers, so an alias would hide one with the same name
+        if register_from_str(name).is_ok()
+            || name.parse::<u16>().is_ok()
+            || constants.inner.contains_key(name)
+        {
+            return Err(format!("invalid alias name: {}", name));
+        }
 
-    // Version
-    out.push(0x01);
+        let register = register.ok_or("missing register")?;
+        register_from_str(register).map_err(|e| e.to_string())?;
 
-    // Width
-    out.extend_from_slice(&width.expect("missing width").to_le_bytes());
+        if let Some(extra) = extra {
+            return Err(format!("unexpected operand: {}", extra));
+        }
 
-    // Height
-    out.extend_from_slice(&height.expect("missing height").to_le_bytes());
-
-    for line in input.lines() {
-        let mut parts = line.trim().split_whitespace();
-
-        if let Some(prefix) = parts.next() {
-            match prefix {
-                "#" | ";" => continue,
-                "WIDTH" | "HEIGHT" => continue,
-                "DRW" => add_instruction_0(&mut out, Opcode::DRW),
-                "FWD" => add_instruction_0(&mut out, Opcode::FWD),
-                "HLT" => add_instruction_0(&mut out, Opcode::HLT),
-                "INC" => add_instruction_1(&mut out, Opcode::INC, parts.next()),
-                "DEC" => add_instruction_1(&mut out, Opcode::DEC, parts.next()),
-                "STO" => add_instruction_2(&mut out, Opcode::STO, parts.next(), parts.next()),
-                "MUL" => add_instruction_2(&mut out, Opcode::MUL, parts.next(), parts.next()),
-                "DIV" => add_instruction_2(&mut out, Opcode::DIV, parts.next(), parts.next()),
-                "ADD" => add_instruction_2(&mut out, Opcode::ADD, parts.next(), parts.next()),
-                "SUB" => add_instruction_2(&mut out, Opcode::SUB, parts.next(), parts.next()),
-                "JNZ" => {
-                    add_instruction_1(&mut out, Opcode::JNZ, parts.next());
-                    add_label(&mut out, &labels, parts.next());
-                }
-                "JGT" => {
-                    add_instruction_2(&mut out, Opcode::JGT, parts.next(), parts.next());
-                    add_label(&mut out, &labels, parts.next());
+        if self.inner.insert(name, register).is_some() {
+            return Err(format!("re-used alias: {}", name));
+        }
+        Ok(())
+    }
+
+    /// The register `input` names, if it's an alias, otherwise `input` itself.
+    fn resolve<'b>(&'b self, input: &'b str) -> &'b str {
+        self.inner.get(input).copied().unwrap_or(input)
+    }
+}
+
+/// Replace `INCLUDE path` lines with the lines of the file at `path`, relative to the including
+/// file or the current directory. Each file is only included once, so includes can't loop.
+/// Included lines are numbered with the line of the top-level `INCLUDE` so errors point at it.
+/// Labels are shared between files, so included routines should keep to local labels under one
+/// uniquely named label.
+fn expand_includes(
+    source: &str,
+    dir: &Path,
+    include_line: Option<usize>,
+    included: &mut HashSet<PathBuf>,
+    errors: &mut Vec<AssembleError>,
+) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+
+    for (number, line) in source.lines().enumerate() {
+        let number = include_line.unwrap_or(number);
+        let mut parts = tokens(line);
+
+        if parts.next() != Some("INCLUDE") {
+            lines.push((number, line.to_string()));
+            continue;
+        }
+
+        let result = parts
+            .next()
+            .ok_or_else(|| "missing include path".to_string())
+            .and_then(|path| {
+                let path = dir.join(path);
+                let canonical = fs::canonicalize(&path)
+                    .map_err(|e| format!("unable to read {}: {}", path.display(), e))?;
+                if !included.insert(canonical.clone()) {
+                    return Ok(None);
                 }
-                "JLT" => {
-                    add_instruction_2(&mut out, Opcode::JLT, parts.next(), parts.next());
-                    add_label(&mut out, &labels, parts.next());
+                let source = fs::read_to_string(&canonical)
+                    .map_err(|e| format!("unable to read {}: {}", path.display(), e))?;
+                Ok(Some((canonical, source)))
+            });
+
+        match result {
+            Ok(Some((path, source))) => {
+                let dir = path.parent().unwrap_or(dir);
+                lines.extend(expand_includes(
+                    &source,
+                    dir,
+                    Some(number),
+                    included,
+                    errors,
+                ));
+            }
+            Ok(None) => {}
+            Err(e) => errors.push(AssembleError::Line {
+                line: number + 1,
+                message: e,
+            }),
+        }
+    }
+
+    lines
+}
+
+/// Expand `REP count` ... `ENDR` blocks, which must be on lines of their own, by repeating the
+/// lines between them `count` times. Blocks can be nested. Each line is paired with its index in
+/// the source so errors still point at where it was written.
+fn expand_repeats<'a>(
+    lines: &[(usize, &'a str)],
+    constants: &Constants,
+    errors: &mut Vec<AssembleError>,
+) -> Vec<(usize, &'a str)> {
+    let mut expanded = Vec::new();
+    // The count, start in `expanded` and line index of each open block, innermost last
+    let mut open: Vec<(u16, usize, usize)> = Vec::new();
+
+    for &(number, line) in lines {
+        let mut parts = tokens(line);
+
+        match parts.next() {
+            Some("REP") => {
+                let count = parse_u16(parts.next(), constants).unwrap_or_else(|e| {
+                    errors.push(AssembleError::Line {
+                        line: number + 1,
+                        message: e,
+                    });
+                    1
+                });
+                open.push((count, expanded.len(), number));
+            }
+            Some("ENDR") => match open.pop() {
+                Some((count, start, _)) => {
+                    let block = expanded.split_off(start);
+                    for _ in 0..count {
+                        expanded.extend_from_slice(&block);
+                    }
                 }
-                "JEQ" => {
-                    add_instruction_2(&mut out, Opcode::JEQ, parts.next(), parts.next());
-                    add_label(&mut out, &labels, parts.next());
+                None => errors.push(AssembleError::Line {
+                    line: number + 1,
+                    message: "ENDR without REP".to_string(),
+                }),
+            },
+            _ => expanded.push((number, line)),
+        }
+    }
+
+    for (_, _, number) in open {
+        errors.push(AssembleError::Line {
+            line: number + 1,
+            message: "REP without ENDR".to_string(),
+        });
+    }
+
+    expanded
+}
+
+/// Find the instructions that can't be reached from the first one or from `indirect_targets`,
+/// returning the index of the first instruction in each unreachable run.
+fn unreachable(program: &[Instruction], indirect_targets: &[usize]) -> Vec<usize> {
+    let mut reachable = vec![false; program.len()];
+    let mut pending = vec![0];
+    pending.extend_from_slice(indirect_targets);
+
+    while let Some(pc) = pending.pop() {
+        if pc >= program.len() || reachable[pc] {
+            continue;
+        }
+        reachable[pc] = true;
+
+        if !matches!(
+            program[pc],
+            Instruction::Halt | Instruction::JumpRegister(_)
+        ) {
+            pending.push(pc + 1);
+        }
+        if program[pc].is_skip() {
+            pending.push(pc + 2);
+        }
+        if let Some(target) = program[pc].jump_target() {
+            pending.push(target);
+        }
+    }
+
+    (0..program.len())
+        .filter(|&pc| !reachable[pc] && (pc == 0 || reachable[pc - 1]))
+        .collect()
+}
+
+/// Assemble each statement on a line into `out`, returning the number of instructions emitted.
+fn assemble_line<'a>(
+    out: &mut Output,
+    labels: &Labels,
+    constants: &Constants,
+    aliases: &Aliases,
+    scope: &mut Option<&'a str>,
+    line: &'a str,
+) -> Result<usize, String> {
+    let mut count = 0;
+    for statement in statements(line) {
+        count += assemble_statement(out, labels, constants, aliases, scope, statement)?;
+    }
+    Ok(count)
+}
+
+/// Assemble a single statement into `out`, returning the number of instructions emitted.
+fn assemble_statement<'a>(
+    out: &mut Output,
+    labels: &Labels,
+    constants: &Constants,
+    aliases: &Aliases,
+    scope: &mut Option<&'a str>,
+    statement: &'a str,
+) -> Result<usize, String> {
+    let mut parts = tokens(statement);
+
+    let prefix = match parts.next() {
+        Some(prefix) => prefix,
+        None => return Ok(0),
+    };
+
+    match prefix {
+        "#" | ";" => return Ok(0),
+        "WIDTH" | "HEIGHT" | "MEMORY" | "BG" | "PALETTE" | "CONST" | "ALIAS" => return Ok(0),
+        "LEA" => add_lea(out, labels, aliases, *scope, &mut parts)?,
+        _ if prefix.ends_with(':') => {
+            // Labels are already processed, just track the current scope
+            if !is_local(prefix) {
+                *scope = Some(prefix);
+            }
+        }
+        _ => {
+            let opcode = Opcode::try_from(prefix).map_err(|_| format!("bad prefix: {}", prefix))?;
+            add_instruction(out, opcode, labels, constants, aliases, *scope, &mut parts)?;
+        }
+    }
+
+    if let Some(extra) = parts.next() {
+        return Err(format!("unexpected operand: {}", extra));
+    }
+
+    // Labels don't emit anything
+    Ok(if prefix.ends_with(':') { 0 } else { 1 })
+}
+
+/// The byte multiple given with `--align n`, if any.
+fn parse_align(mut args: impl Iterator<Item = String>) -> Result<Option<usize>, String> {
+    if args.any(|arg| arg == "--align") {
+        let n = args.next().ok_or("missing alignment")?;
+        match n.parse() {
+            Ok(align) if align > 0 => Ok(Some(align)),
+            _ => Err(format!("invalid alignment: {}", n)),
+        }
+    } else {
+        Ok(None)
+    }
+}
+
+/// Print each opcode's mnemonic, byte and operand kinds as tab separated columns, for tools that
+/// need to know the instruction set.
+fn print_spec() {
+    println!("mnemonic\tbyte\toperands");
+    for &opcode in Opcode::all() {
+        let operands: Vec<&str> = opcode
+            .operand_kinds()
+            .iter()
+            .map(|kind| match kind {
+                OperandKind::Register => "register",
+                OperandKind::Value => "value",
+                OperandKind::Address => "address",
+                OperandKind::AngleUnit => "angle_unit",
+                OperandKind::Condition => "condition",
+            })
+            .collect();
+        println!(
+            "{}\t{:#04x}\t{}",
+            opcode.mnemonic(),
+            opcode as u8,
+            operands.join(",")
+        );
+    }
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    // Print the instruction set instead of assembling anything
+    if std::env::args().skip(1).any(|arg| arg == "--spec") {
+        print_spec();
+        return Ok(());
+    }
+    // Only validate the program, don't write program.bin
+    let check = std::env::args().skip(1).any(|arg| arg == "--check");
+    // Also write program.map, mapping each instruction to its source line
+    let write_map = std::env::args().skip(1).any(|arg| arg == "--map");
+    // Write multi-byte values most significant byte first
+    let endianness = if std::env::args().skip(1).any(|arg| arg == "--big-endian") {
+        Endianness::Big
+    } else {
+        Endianness::Little
+    };
+    // Write immediate values as varints, so small ones take a single byte
+    let varint = std::env::args().skip(1).any(|arg| arg == "--varint");
+    // Append the source, with includes expanded, after the instructions so it can be recovered
+    // from program.bin
+    let embed_source = std::env::args().skip(1).any(|arg| arg == "--embed-source");
+    // Pad the output with HLTs to a multiple of this many bytes
+    let align = parse_align(std::env::args().skip(1))?;
+
+    let input = read_stdin()?;
+
+    let mut errors = Vec::new();
+
+    let source = expand_includes(
+        &input,
+        Path::new("."),
+        None,
+        &mut HashSet::new(),
+        &mut errors,
+    );
+    let lines: Vec<(usize, &str)> = source
+        .iter()
+        .map(|(number, line)| (*number, line.as_str()))
+        .collect();
+
+    // Constants come first since they can be used as repeat counts
+    let constants = Constants::new(&lines, &mut errors);
+    let lines = expand_repeats(&lines, &constants, &mut errors);
+    let aliases = Aliases::new(&lines, &constants, &mut errors);
+    let labels = Labels::new(&lines, &mut errors);
+
+    // Find width, height and the optional memory size and background
+    let mut width = None;
+    let mut height = None;
+    let mut memory = None;
+    let mut background = None;
+    let mut palette = Vec::new();
+
+    for &(number, line) in &lines {
+        for statement in statements(line) {
+            let mut parts = tokens(statement);
+
+            if let Some(prefix) = parts.next() {
+                if prefix == "PALETTE" {
+                    for color in parts {
+                        let result = parse_color(Some(color)).and_then(|color| {
+                            if palette.len() < PALETTE_SIZE {
+                                palette.push(color);
+                                Ok(())
+                            } else {
+                                Err(format!("more than {} palette colors", PALETTE_SIZE))
+                            }
+                        });
+                        if let Err(e) = result {
+                            errors.push(AssembleError::Line {
+                                line: number + 1,
+                                message: e,
+                            });
+                        }
+                    }
+                    continue;
                 }
-                "JNE" => {
-                    add_instruction_2(&mut out, Opcode::JNE, parts.next(), parts.next());
-                    add_label(&mut out, &labels, parts.next());
+
+                if prefix == "BG" {
+                    match parse_color(parts.next()) {
+                        Ok(color) => background = Some(color),
+                        Err(e) => errors.push(AssembleError::Line {
+                            line: number + 1,
+                            message: e,
+                        }),
+                    }
+                    continue;
                 }
-                _ => {
-                    if prefix.ends_with(':') {
-                        // Labels are already processed, move on
+
+                let dimension = match prefix {
+                    "WIDTH" => &mut width,
+                    "HEIGHT" => &mut height,
+                    "MEMORY" => &mut memory,
+                    _ => continue,
+                };
+
+                let value = parse_u16(parts.next(), &constants).and_then(|value| {
+                    if prefix != "MEMORY" && !(1..=MAX_DIMENSION).contains(&value) {
+                        Err(format!("{} must be from 1 to {}", prefix, MAX_DIMENSION))
                     } else {
-                        panic!("bad prefix: {}", prefix)
+                        Ok(value)
                     }
+                });
+                match value {
+                    Ok(value) => *dimension = Some(value),
+                    Err(e) => errors.push(AssembleError::Line {
+                        line: number + 1,
+                        message: e,
+                    }),
                 }
             }
         }
+    }
+
+    let mut out = Output {
+        bytes: Vec::new(),
+        endianness,
+        varint,
+        wide_addresses: labels.need_wide_addresses(),
+        indirect_targets: Vec::new(),
+    };
+
+    // Version
+    out.push(VERSION);
+
+    // Byte order
+    out.push(endianness as u8);
+
+    // Width
+    match width {
+        Some(width) => out.push_u16(width),
+        None => errors.push(AssembleError::MissingWidth),
+    }
+
+    // Height
+    match height {
+        Some(height) => out.push_u16(height),
+        None => errors.push(AssembleError::MissingHeight),
+    }
+
+    // Memory, in words
+    out.push_u16(memory.unwrap_or(0));
+
+    // Background, as 0x00RRGGBB
+    out.push_u32(background.unwrap_or(0));
+
+    // Palette, as a length and then 0x00RRGGBB colors
+    out.push(palette.len() as u8);
+    for color in palette {
+        out.push_u32(color);
+    }
+
+    // Flags
+    let mut flags = 0;
+    if varint {
+        flags |= FLAG_VARINT;
+    }
+    if out.wide_addresses {
+        flags |= FLAG_WIDE_ADDRESSES;
+    }
+    // The source flag is set once the source is appended, so the checks below decode without it
+    let flags_at = out.len();
+    out.push(flags);
+
+    // The non-local label that local label references currently resolve against
+    let mut scope = None;
+
+    // The source line of each instruction, indexed by instruction
+    let mut source_map = Vec::new();
+
+    for &(number, line) in &lines {
+        match assemble_line(&mut out, &labels, &constants, &aliases, &mut scope, line) {
+            Ok(count) => source_map.extend(std::iter::repeat_n(number + 1, count)),
+            Err(e) => errors.push(AssembleError::Line {
+                line: number + 1,
+                message: e,
+            }),
+        }
+    }
 
-        assert!(parts.next().is_none());
+    if !errors.is_empty() {
+        for e in errors {
+            eprintln!("error: {}", e);
+        }
+        std::process::exit(1);
+    }
+
+    let (_, program) = decode(&out.bytes)?;
+    if program.last().is_some_and(Instruction::is_skip) {
+        let line = source_map[program.len() - 1];
+        return Err(format!("line {}: nothing after the skip to skip", line).into());
+    }
+    for pc in unreachable(&program, &out.indirect_targets) {
+        eprintln!("warning: line {}: unreachable code", source_map[pc]);
+    }
+
+    // Labels and constants are only looked up, never iterated, so the output doesn't depend on
+    // hash order and is the same on every run
+    if let Some(align) = align {
+        while !out.len().is_multiple_of(align) {
+            out.push(Opcode::HLT as u8);
+        }
+    }
+
+    // Padding only aligns the instructions, the source and its length follow
+    if embed_source {
+        // Included files might not be around when it's recovered, so their lines are embedded
+        let embedded: String = source
+            .iter()
+            .map(|(_, line)| format!("{}\n", line))
+            .collect();
+        let len = u32::try_from(embedded.len()).map_err(|_| "source too long to embed")?;
+        out.bytes.extend_from_slice(embedded.as_bytes());
+        out.push_u32(len);
+        out[flags_at] |= FLAG_SOURCE;
+    }
+
+    if check {
+        eprintln!("ok: {} bytes", out.len());
+        return Ok(());
+    }
+
+    let mut file = File::create("program.bin")?;
+    file.write_all(&out.bytes)?;
+
+    if write_map {
+        let mut file = File::create("program.map")?;
+        for (index, line) in source_map.iter().enumerate() {
+            writeln!(file, "{} {}", index, line)?;
+        }
     }
 
-    let mut file = File::create("program.bin").expect("unable to create file");
-    file.write_all(&out).expect("unable to write to file");
+    Ok(())
 }
@@ -0,0 +1,66 @@
+use std::error::Error;
+use std::io::{stdin, Read};
+use std::time::Instant;
+
+use drawer::compile::CompiledProgram;
+use drawer::instruction::decode;
+use drawer::vm::{Limits, Vm, VmError};
+
+/// Give up on a run after this many steps, so programs that never halt still finish.
+const MAX_STEPS: usize = 100_000_000;
+
+/// Decode a program from STDIN, run it repeatedly without drawing, and report how many
+/// instructions per second the VM executes.
+///
+/// ```text
+/// bench [runs] < program.bin
+/// ```
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let runs: usize = match std::env::args().nth(1) {
+        Some(runs) => runs
+            .parse()
+            .map_err(|_| format!("invalid number of runs: {}", runs))?,
+        None => 10,
+    };
+
+    let mut input = Vec::new();
+    stdin().read_to_end(&mut input)?;
+
+    let (header, program) = decode(&input)?;
+    let program = CompiledProgram::new(&program);
+
+    let mut instructions = 0;
+    let start = Instant::now();
+
+    let mut vm = Vm::default()
+        .with_memory(header.memory)
+        .with_palette(header.palette)
+        .quiet();
+    for _ in 0..runs {
+        vm.reset();
+        match vm.run(&program, &Limits::cycles(MAX_STEPS)) {
+            Ok(_) | Err(VmError::CycleLimit { .. }) => {}
+            Err(e) => return Err(e.into()),
+        }
+        instructions += vm.cycles();
+    }
+
+    let elapsed = start.elapsed();
+
+    println!("runs:         {}", runs);
+    println!("instructions: {}", instructions);
+    println!("elapsed:      {:.3}s", elapsed.as_secs_f64());
+    println!(
+        "per second:   {:.0}",
+        instructions as f64 / elapsed.as_secs_f64()
+    );
+
+    Ok(())
+}
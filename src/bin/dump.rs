@@ -0,0 +1,59 @@
+use std::error::Error;
+use std::io::{stdin, Read};
+
+use drawer::instruction::decode;
+
+/// Decode a program from STDIN and write it to STDOUT as JSON.
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let mut input = Vec::new();
+    stdin().read_to_end(&mut input)?;
+
+    let (header, program) = decode(&input)?;
+
+    let instructions: Vec<String> = program
+        .iter()
+        .map(|instruction| format!("    {}", instruction.to_json()))
+        .collect();
+
+    println!("{{");
+    println!("  \"width\": {},", header.width);
+    println!("  \"height\": {},", header.height);
+    println!("  \"memory\": {},", header.memory);
+    println!("  \"background\": {},", header.background);
+    println!("  \"endianness\": \"{}\",", header.endianness.name());
+    println!("  \"palette\": {:?},", header.palette);
+    println!("  \"varint\": {},", header.varint);
+    println!("  \"wide_addresses\": {},", header.wide_addresses);
+    match &header.source {
+        Some(source) => println!("  \"source\": {},", json_string(source)),
+        None => println!("  \"source\": null,"),
+    }
+    println!("  \"instructions\": [\n{}\n  ]", instructions.join(",\n"));
+    println!("}}");
+
+    Ok(())
+}
+
+/// `s` as a quoted JSON string.
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -0,0 +1,70 @@
+use std::error::Error;
+use std::io::{stdin, Read};
+
+use drawer::instruction::decode_with_offsets;
+
+/// How many header or source bytes to show on each line.
+const HEADER_BYTES_PER_LINE: usize = 8;
+
+/// Decode a program from STDIN and print each instruction's offset and bytes beside its
+/// disassembly.
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let mut input = Vec::new();
+    stdin().read_to_end(&mut input)?;
+
+    let (header, program, end) = decode_with_offsets(&input)?;
+
+    // Everything before the first instruction is the header
+    let header_len = program.first().map_or(end, |&(offset, _)| offset);
+    print_bytes(0, &input[..header_len], "header");
+
+    for (index, &(offset, instruction)) in program.iter().enumerate() {
+        let end = program.get(index + 1).map_or(end, |&(offset, _)| offset);
+        print_line(
+            offset,
+            &input[offset..end],
+            &format!("{}: {}", index, instruction),
+        );
+    }
+
+    // Embedded source, followed by its four byte length
+    if let Some(source) = header.source {
+        let length_at = input.len() - 4;
+        print_bytes(end, &input[end..length_at], "source");
+        print_line(
+            length_at,
+            &input[length_at..],
+            &format!("source length {}", source.len()),
+        );
+    }
+
+    Ok(())
+}
+
+/// Print `bytes` starting at `offset` over as many lines as they need, with `label` beside the
+/// first.
+fn print_bytes(offset: usize, bytes: &[u8], label: &str) {
+    for (i, chunk) in bytes.chunks(HEADER_BYTES_PER_LINE).enumerate() {
+        let label = if i == 0 { label } else { "" };
+        print_line(offset + i * HEADER_BYTES_PER_LINE, chunk, label);
+    }
+}
+
+fn print_line(offset: usize, bytes: &[u8], annotation: &str) {
+    let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let line = format!(
+        "{:06x}  {:<width$}  {}",
+        offset,
+        hex.join(" "),
+        annotation,
+        width = HEADER_BYTES_PER_LINE * 3 - 1
+    );
+    println!("{}", line.trim_end());
+}
@@ -1,15 +1,83 @@
+use core::convert::TryFrom;
+
+#[cfg(not(feature = "std"))]
+use alloc::{vec, vec::Vec};
+
+use crate::instruction::MAX_DIMENSION;
+use crate::math;
+use crate::vm::{DrawEvent, PenState};
+
 pub struct Buffer {
     buffer: Vec<u32>,
     width: usize,
     height: usize,
+    /// The color of pixels nothing has been drawn to
+    background: u32,
+    /// The palette index of each pixel's color, when tracked with [`Buffer::track_palette`]
+    indices: Option<Vec<Option<u8>>>,
 }
 
 impl Buffer {
     pub fn new(width: usize, height: usize) -> Self {
+        Self::new_with_background(width, height, 0)
+    }
+
+    pub fn new_with_background(width: usize, height: usize, background: u32) -> Self {
         Self {
-            buffer: vec![0; width * height],
+            buffer: vec![background; width * height],
             width,
             height,
+            background,
+            indices: None,
+        }
+    }
+
+    /// Start recording which palette index each pixel is drawn with, for
+    /// [`Buffer::cycle_palette`]. Pixels already drawn have none.
+    pub fn track_palette(&mut self) {
+        self.indices = Some(vec![None; self.width * self.height]);
+    }
+
+    /// Record that the pixel at `x`, `y` was drawn with palette color `index`. Drawing over it
+    /// again forgets the index. Does nothing unless the palette is tracked.
+    pub fn set_palette_index(&mut self, x: usize, y: usize, index: Option<u8>) {
+        let l = y * self.width + x;
+        if let Some(indices) = &mut self.indices {
+            indices[l] = index;
+        }
+    }
+
+    /// Recolor every pixel drawn with a palette color to the color `shift` places further along
+    /// `palette`, wrapping around, to animate the palette without redrawing.
+    pub fn cycle_palette(&mut self, palette: &[u32], shift: usize) {
+        let indices = match &self.indices {
+            Some(indices) if !palette.is_empty() => indices,
+            _ => return,
+        };
+        for (pixel, index) in self.buffer.iter_mut().zip(indices) {
+            if let Some(index) = index {
+                *pixel = palette[(usize::from(*index) + shift) % palette.len()];
+            }
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn background(&self) -> u32 {
+        self.background
+    }
+
+    /// Set every pixel back to the background color.
+    pub fn clear(&mut self) {
+        self.buffer.fill(self.background);
+        if let Some(indices) = &mut self.indices {
+            indices.fill(None);
         }
     }
 
@@ -17,9 +85,317 @@ impl Buffer {
         let l = y * self.width + x;
         debug_assert!(l <= self.width * self.height);
         self.buffer[l] = color;
+        self.forget_index(l);
+    }
+
+    fn forget_index(&mut self, l: usize) {
+        if let Some(indices) = &mut self.indices {
+            indices[l] = None;
+        }
+    }
+
+    /// The pixel `x`, `y` from the center of the buffer, or `None` if that's outside it.
+    pub fn centered(&self, x: isize, y: isize) -> Option<(usize, usize)> {
+        let center = (self.width as isize / 2, self.height as isize / 2);
+        self.offset(center, x, y)
+    }
+
+    /// The pixel `x`, `y` from the pixel `origin`, or `None` if that's outside the buffer.
+    pub fn offset(&self, origin: (isize, isize), x: isize, y: isize) -> Option<(usize, usize)> {
+        let x = usize::try_from(origin.0.saturating_add(x)).ok()?;
+        let y = usize::try_from(origin.1.saturating_add(y)).ok()?;
+        if x < self.width && y < self.height {
+            Some((x, y))
+        } else {
+            None
+        }
+    }
+
+    /// The color of the pixel `x`, `y` from the center of the buffer, or `None` if that's outside
+    /// it.
+    pub fn pixel_centered(&self, x: isize, y: isize) -> Option<u32> {
+        let (x, y) = self.centered(x, y)?;
+        Some(self.buffer[y * self.width + x])
+    }
+
+    /// Draw `event` the way the window does by default, with 0,0 in the center and anything
+    /// outside the buffer clipped.
+    pub fn draw_event(&mut self, event: &DrawEvent) {
+        if event.kind == PenState::Up {
+            return;
+        }
+
+        let (x, y) = event.pixel();
+        // Fills start from a single pixel however wide the pen is
+        let width = if event.kind == PenState::Fill {
+            1
+        } else {
+            event.width
+        };
+        for (dx, dy) in dot(width) {
+            if let Some((x, y)) = self.centered(x.saturating_add(dx), y.saturating_add(dy)) {
+                match event.color >> 24 {
+                    _ if event.kind == PenState::Fill => self.flood_fill(x, y, event.color),
+                    0x00 | 0xff => self.set_pixel(x, y, event.color),
+                    _ => self.blend_pixel(x, y, event.color),
+                }
+            }
+        }
+    }
+
+    /// Blend `argb` over the existing pixel using its high byte as the alpha channel.
+    pub fn blend_pixel(&mut self, x: usize, y: usize, argb: u32) {
+        let l = y * self.width + x;
+        debug_assert!(l <= self.width * self.height);
+
+        let alpha = argb >> 24;
+        let existing = self.buffer[l];
+        let channel = |shift: u32| {
+            let src = (argb >> shift) & 0xff;
+            let dst = (existing >> shift) & 0xff;
+            ((src * alpha + dst * (0xff - alpha)) / 0xff) << shift
+        };
+
+        self.buffer[l] = channel(16) | channel(8) | channel(0);
+        self.forget_index(l);
+    }
+
+    /// Draw a one pixel wide line from `x0`, `y0` to `x1`, `y1` inclusive, blending `color` when
+    /// it's translucent. Anything outside the buffer is clipped.
+    pub fn draw_line(&mut self, (x0, y0): (isize, isize), (x1, y1): (isize, isize), color: u32) {
+        let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+        let (sx, sy) = (if x0 < x1 { 1 } else { -1 }, if y0 < y1 { 1 } else { -1 });
+        let (mut x, mut y) = (x0, y0);
+        let mut error = dx + dy;
+
+        loop {
+            if let (Ok(px), Ok(py)) = (usize::try_from(x), usize::try_from(y)) {
+                if px < self.width && py < self.height {
+                    match color >> 24 {
+                        0x00 | 0xff => self.set_pixel(px, py, color),
+                        _ => self.blend_pixel(px, py, color),
+                    }
+                }
+            }
+            if (x, y) == (x1, y1) {
+                break;
+            }
+            if 2 * error >= dy {
+                error += dy;
+                x += sx;
+            }
+            if 2 * error <= dx {
+                error += dx;
+                y += sy;
+            }
+        }
+    }
+
+    /// Draw a disc `radius` pixels across centered on `cx`, `cy`, blending pixels on its edge by
+    /// how much of them it covers so it looks smooth. Pixel `x`, `y` covers `x..x + 1`,
+    /// `y..y + 1`, and anything outside the buffer is clipped.
+    pub fn draw_disc_aa(&mut self, cx: f64, cy: f64, radius: f64, color: u32) {
+        // An alpha of 0 is treated as opaque so plain 0xRRGGBB colors overwrite
+        let alpha = match color >> 24 {
+            0x00 => 0xff,
+            alpha => alpha,
+        };
+        let rgb = color & 0xff_ffff;
+
+        let clamp = |n: f64, size: usize| (n.max(0.0) as usize).min(size);
+        let (left, right) = (
+            clamp(cx - radius, self.width),
+            clamp(cx + radius + 1.0, self.width),
+        );
+        let (top, bottom) = (
+            clamp(cy - radius, self.height),
+            clamp(cy + radius + 1.0, self.height),
+        );
+
+        for y in top..bottom {
+            for x in left..right {
+                let (dx, dy) = (x as f64 + 0.5 - cx, y as f64 + 0.5 - cy);
+                // Roughly the fraction of the pixel inside the edge, which is 1 pixel wide
+                let coverage = (radius + 0.5 - math::sqrt(dx * dx + dy * dy)).min(1.0);
+                if coverage <= 0.0 {
+                    continue;
+                }
+                match (f64::from(alpha) * coverage) as u32 {
+                    0 => {}
+                    0xff => self.set_pixel(x, y, rgb),
+                    alpha => self.blend_pixel(x, y, alpha << 24 | rgb),
+                }
+            }
+        }
+    }
+
+    /// Add each RGB channel of `color` to the existing pixel, saturating at white.
+    pub fn add_pixel(&mut self, x: usize, y: usize, color: u32) {
+        let l = y * self.width + x;
+        debug_assert!(l <= self.width * self.height);
+
+        let [b1, g1, r1, _] = self.buffer[l].to_le_bytes();
+        let [b2, g2, r2, _] = color.to_le_bytes();
+
+        self.buffer[l] = u32::from_le_bytes([
+            b1.saturating_add(b2),
+            g1.saturating_add(g2),
+            r1.saturating_add(r2),
+            0,
+        ]);
+        self.forget_index(l);
+    }
+
+    /// Copy all of `src` into this buffer with its top left corner at `dst_x`, `dst_y`. Anything
+    /// that doesn't fit is clipped.
+    pub fn blit(&mut self, src: &Buffer, dst_x: usize, dst_y: usize) {
+        let width = src.width.min(self.width.saturating_sub(dst_x));
+        let height = src.height.min(self.height.saturating_sub(dst_y));
+
+        for y in 0..height {
+            let from = y * src.width;
+            let to = (dst_y + y) * self.width + dst_x;
+            self.buffer[to..to + width].copy_from_slice(&src.buffer[from..from + width]);
+            if let Some(indices) = &mut self.indices {
+                match &src.indices {
+                    Some(src) => indices[to..to + width].copy_from_slice(&src[from..from + width]),
+                    None => indices[to..to + width].fill(None),
+                }
+            }
+        }
+    }
+
+    /// Replace the pixel at `x`, `y` and every pixel of the same color connected to it with
+    /// `color`.
+    pub fn flood_fill(&mut self, x: usize, y: usize, color: u32) {
+        self.flood_fill_with_index(x, y, color, None);
+    }
+
+    /// Like [`Buffer::flood_fill`], recording palette color `index` for the filled pixels.
+    pub fn flood_fill_with_index(&mut self, x: usize, y: usize, color: u32, index: Option<u8>) {
+        let target = self.buffer[y * self.width + x];
+        if target == color {
+            return;
+        }
+
+        // Each seed fills the run of target pixels it's in, then seeds the rows above and below
+        let mut seeds = vec![(x, y)];
+        while let Some((x, y)) = seeds.pop() {
+            let row = y * self.width;
+            if self.buffer[row + x] != target {
+                continue;
+            }
+
+            let mut left = x;
+            while left > 0 && self.buffer[row + left - 1] == target {
+                left -= 1;
+            }
+            let mut right = x;
+            while right + 1 < self.width && self.buffer[row + right + 1] == target {
+                right += 1;
+            }
+
+            for x in left..=right {
+                self.buffer[row + x] = color;
+                if let Some(indices) = &mut self.indices {
+                    indices[row + x] = index;
+                }
+                if y > 0 && self.buffer[row - self.width + x] == target {
+                    seeds.push((x, y - 1));
+                }
+                if y + 1 < self.height && self.buffer[row + self.width + x] == target {
+                    seeds.push((x, y + 1));
+                }
+            }
+        }
+    }
+
+    /// Grow the buffer to `width` by `height`, moving the existing pixels right by `dx` and down by
+    /// `dy`. New pixels are the background color.
+    pub fn resize(&mut self, width: usize, height: usize, dx: usize, dy: usize) {
+        let mut resized = Buffer::new_with_background(width, height, self.background);
+        if self.indices.is_some() {
+            resized.track_palette();
+        }
+        resized.blit(self, dx, dy);
+        *self = resized;
+    }
+
+    /// Grow the buffer so `x`, `y` lies inside it, growing by at least half again in each
+    /// direction that needs it so repeated calls don't reallocate for every pixel. The buffer
+    /// never grows past [`MAX_DIMENSION`] on either side, leaving positions further out still
+    /// outside it. Returns how far the existing pixels moved right and down.
+    pub fn ensure_contains(&mut self, x: isize, y: isize) -> (usize, usize) {
+        let grow = |position: isize, size: usize| -> (usize, usize) {
+            let room = usize::from(MAX_DIMENSION).saturating_sub(size);
+            let slack = (size / 2).max(1);
+            if position < 0 {
+                (position.unsigned_abs().max(slack).min(room), 0)
+            } else if position as usize >= size {
+                (0, (position as usize + 1 - size).max(slack).min(room))
+            } else {
+                (0, 0)
+            }
+        };
+
+        let (left, right) = grow(x, self.width);
+        let (top, bottom) = grow(y, self.height);
+
+        if left + right + top + bottom > 0 {
+            self.resize(
+                self.width + left + right,
+                self.height + top + bottom,
+                left,
+                top,
+            );
+        }
+
+        (left, top)
     }
 
     pub fn buffer(&self) -> &[u32] {
         &self.buffer
     }
+
+    /// The pixels as opaque RGBA bytes, row by row, the layout of an HTML canvas `ImageData`.
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        self.buffer
+            .iter()
+            .flat_map(|pixel| {
+                let [_, r, g, b] = pixel.to_be_bytes();
+                [r, g, b, 0xff]
+            })
+            .collect()
+    }
+
+    /// Write the buffer as a binary PPM image, ignoring any alpha channel.
+    #[cfg(feature = "std")]
+    pub fn write_ppm<W: std::io::Write>(&self, w: &mut W) -> std::io::Result<()> {
+        writeln!(w, "P6 {} {} 255", self.width, self.height)?;
+        let pixels: Vec<u8> = self
+            .buffer
+            .iter()
+            .flat_map(|pixel| {
+                let [_, r, g, b] = pixel.to_be_bytes();
+                [r, g, b]
+            })
+            .collect();
+        w.write_all(&pixels)
+    }
+}
+
+/// The offsets of the pixels in a filled dot `width` pixels across. Widths of 0 and 1 are a
+/// single pixel at 0,0.
+pub fn dot(width: u16) -> impl Iterator<Item = (isize, isize)> {
+    let width = width.max(1) as isize;
+    let (min, max) = (-(width - 1) / 2, width / 2);
+    let center = (min + max) as f64 / 2.0;
+    let radius = width as f64 / 2.0;
+
+    (min..=max)
+        .flat_map(move |y| (min..=max).map(move |x| (x, y)))
+        .filter(move |&(x, y)| {
+            let (dx, dy) = (x as f64 - center, y as f64 - center);
+            dx * dx + dy * dy <= radius * radius
+        })
 }
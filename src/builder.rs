@@ -0,0 +1,196 @@
+//! Build programs in Rust instead of assembly.
+
+use core::fmt;
+
+#[cfg(not(feature = "std"))]
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+#[cfg(feature = "std")]
+use std::collections::BTreeMap;
+
+use crate::instruction::{encode, Address, Condition, Header, Instruction, Register, Value};
+
+/// Returned by [`ProgramBuilder::build`] when the program's labels don't add up.
+#[derive(Debug, PartialEq)]
+pub enum BuildError {
+    /// A jump was made to a label that was never placed
+    UnknownLabel(String),
+    /// The same label was placed twice
+    DuplicateLabel(String),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::UnknownLabel(label) => write!(f, "unknown label: {}", label),
+            BuildError::DuplicateLabel(label) => write!(f, "re-used label: {}", label),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BuildError {}
+
+/// Builds the same bytecode the assembler would for a program, resolving jumps to named labels
+/// when it's built.
+///
+/// ```
+/// use drawer::builder::ProgramBuilder;
+/// use drawer::instruction::{Header, UintRegister::B};
+///
+/// let program = ProgramBuilder::new(Header::new(64, 64))
+///     .draw()
+///     .sto(B, 10)
+///     .label("loop")
+///     .fwd()
+///     .dec(B)
+///     .jnz(B, "loop")
+///     .hlt()
+///     .build()
+///     .unwrap();
+/// ```
+pub struct ProgramBuilder {
+    header: Header,
+    program: Vec<Instruction>,
+    /// The instruction index each label was placed at
+    labels: BTreeMap<String, u32>,
+    /// Jumps waiting for their label's address, by instruction index
+    jumps: Vec<(usize, String)>,
+    duplicate_label: Option<String>,
+}
+
+impl ProgramBuilder {
+    pub fn new(header: Header) -> Self {
+        ProgramBuilder {
+            header,
+            program: Vec::new(),
+            labels: BTreeMap::new(),
+            jumps: Vec::new(),
+            duplicate_label: None,
+        }
+    }
+
+    /// Add any instruction. Jumps should be added with the methods taking a label instead.
+    pub fn instruction(&mut self, instruction: Instruction) -> &mut Self {
+        self.program.push(instruction);
+        self
+    }
+
+    /// Mark the next instruction as the target of jumps to `name`.
+    pub fn label(&mut self, name: &str) -> &mut Self {
+        let addr = self.program.len() as u32;
+        if self.labels.insert(name.to_string(), addr).is_some() && self.duplicate_label.is_none() {
+            self.duplicate_label = Some(name.to_string());
+        }
+        self
+    }
+
+    pub fn draw(&mut self) -> &mut Self {
+        self.instruction(Instruction::Draw)
+    }
+
+    pub fn fwd(&mut self) -> &mut Self {
+        self.instruction(Instruction::Forward)
+    }
+
+    pub fn hlt(&mut self) -> &mut Self {
+        self.instruction(Instruction::Halt)
+    }
+
+    pub fn sto(&mut self, register: impl Into<Register>, value: impl Into<Value>) -> &mut Self {
+        self.instruction(Instruction::Store(register.into(), value.into()))
+    }
+
+    pub fn add(&mut self, register: impl Into<Register>, value: impl Into<Value>) -> &mut Self {
+        self.instruction(Instruction::Add(register.into(), value.into()))
+    }
+
+    pub fn sub(&mut self, register: impl Into<Register>, value: impl Into<Value>) -> &mut Self {
+        self.instruction(Instruction::Sub(register.into(), value.into()))
+    }
+
+    pub fn inc(&mut self, register: impl Into<Register>) -> &mut Self {
+        self.instruction(Instruction::Increment(register.into()))
+    }
+
+    pub fn dec(&mut self, register: impl Into<Register>) -> &mut Self {
+        self.instruction(Instruction::Decrement(register.into()))
+    }
+
+    pub fn jnz(&mut self, register: impl Into<Register>, label: &str) -> &mut Self {
+        self.jump(Instruction::JumpIfNonZero(register.into(), 0.into()), label)
+    }
+
+    pub fn jeq(
+        &mut self,
+        register: impl Into<Register>,
+        value: impl Into<Value>,
+        label: &str,
+    ) -> &mut Self {
+        let jump = Instruction::JumpIfEqual(register.into(), value.into(), 0.into());
+        self.jump(jump, label)
+    }
+
+    pub fn jne(
+        &mut self,
+        register: impl Into<Register>,
+        value: impl Into<Value>,
+        label: &str,
+    ) -> &mut Self {
+        let jump = Instruction::JumpIfNotEqual(register.into(), value.into(), 0.into());
+        self.jump(jump, label)
+    }
+
+    pub fn jgt(
+        &mut self,
+        register: impl Into<Register>,
+        value: impl Into<Value>,
+        label: &str,
+    ) -> &mut Self {
+        let jump = Instruction::JumpIfGreaterThan(register.into(), value.into(), 0.into());
+        self.jump(jump, label)
+    }
+
+    pub fn jlt(
+        &mut self,
+        register: impl Into<Register>,
+        value: impl Into<Value>,
+        label: &str,
+    ) -> &mut Self {
+        let jump = Instruction::JumpIfLessThan(register.into(), value.into(), 0.into());
+        self.jump(jump, label)
+    }
+
+    pub fn jf(&mut self, condition: Condition, label: &str) -> &mut Self {
+        self.jump(Instruction::JumpIfFlag(condition, 0.into()), label)
+    }
+
+    fn jump(&mut self, jump: Instruction, label: &str) -> &mut Self {
+        self.jumps.push((self.program.len(), label.to_string()));
+        self.instruction(jump)
+    }
+
+    /// Resolve the labels and encode the program.
+    pub fn build(&self) -> Result<Vec<u8>, BuildError> {
+        if let Some(label) = &self.duplicate_label {
+            return Err(BuildError::DuplicateLabel(label.clone()));
+        }
+
+        let mut header = self.header.clone();
+        let mut program = self.program.clone();
+        for (index, label) in &self.jumps {
+            let addr = self
+                .labels
+                .get(label)
+                .ok_or_else(|| BuildError::UnknownLabel(label.clone()))?;
+            program[*index].set_jump_target(Address(*addr));
+            // Two byte addresses only reach the first 65536 instructions
+            header.wide_addresses |= *addr > u32::from(u16::MAX);
+        }
+
+        Ok(encode(&header, &program))
+    }
+}
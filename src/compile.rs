@@ -0,0 +1,131 @@
+use core::cmp::Ordering;
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+use crate::instruction::{Condition, Instruction, Register, Value};
+use crate::math;
+
+/// How many units in the last place apart two floats can be and still compare equal.
+const ULPS: f64 = 4.0;
+
+/// Whether `a` and `b` are within a few units in the last place of the larger of them, so
+/// rounding error doesn't decide a comparison whatever their magnitude. Below a magnitude of 1
+/// the tolerance stays at its size for 1, so tiny values still equal zero.
+pub(crate) fn approx_eq(a: f64, b: f64) -> bool {
+    if a == b {
+        return true;
+    }
+    // An infinite scale would make every finite value equal to infinity
+    if !a.is_finite() || !b.is_finite() {
+        return false;
+    }
+    let scale = math::abs(a).max(math::abs(b)).max(1.0);
+    math::abs(a - b) <= ULPS * f64::EPSILON * scale
+}
+
+/// Compare two floats, treating values that are [`approx_eq`] as equal.
+pub(crate) fn compare_floats(a: f64, b: f64) -> Option<Ordering> {
+    if approx_eq(a, b) {
+        Some(Ordering::Equal)
+    } else {
+        a.partial_cmp(&b)
+    }
+}
+
+/// An instruction as the VM executes it. Jumps are split by the kind of register they compare
+/// and carry their target as an instruction index.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Op {
+    /// Any instruction other than a jump
+    Instruction(Instruction),
+    JumpUint {
+        register: usize,
+        condition: Condition,
+        value: Value,
+        target: usize,
+    },
+    JumpFloat {
+        register: usize,
+        condition: Condition,
+        value: Value,
+        target: usize,
+    },
+    /// Jump on the result of the last `CMP`
+    JumpFlag { condition: Condition, target: usize },
+}
+
+/// A decoded program lowered for [`Vm::step`](crate::vm::Vm::step), so jumps don't convert their
+/// address or match on the kind of register they compare each time they run. Skips become jumps
+/// over the next instruction.
+#[derive(Debug, PartialEq)]
+pub struct CompiledProgram {
+    ops: Vec<Op>,
+    /// The instructions the ops were lowered from
+    instructions: Vec<Instruction>,
+}
+
+impl CompiledProgram {
+    pub fn new(program: &[Instruction]) -> Self {
+        CompiledProgram {
+            ops: program
+                .iter()
+                .enumerate()
+                .map(|(pc, instruction)| lower(pc, instruction))
+                .collect(),
+            instructions: program.to_vec(),
+        }
+    }
+
+    /// The instruction at `pc` as it was before lowering.
+    pub fn instruction(&self, pc: usize) -> &Instruction {
+        &self.instructions[pc]
+    }
+
+    pub(crate) fn op(&self, pc: usize) -> &Op {
+        &self.ops[pc]
+    }
+
+    /// The number of instructions in the program.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+fn lower(pc: usize, instruction: &Instruction) -> Op {
+    let (register, condition, value, target) = match *instruction {
+        Instruction::JumpIfNonZero(r, a) => (r, Condition::NotEqual, Value::Uint(0), a.into()),
+        Instruction::JumpIfEqual(r, v, a) => (r, Condition::Equal, v, a.into()),
+        Instruction::JumpIfNotEqual(r, v, a) => (r, Condition::NotEqual, v, a.into()),
+        Instruction::JumpIfGreaterThan(r, v, a) => (r, Condition::GreaterThan, v, a.into()),
+        Instruction::JumpIfLessThan(r, v, a) => (r, Condition::LessThan, v, a.into()),
+        Instruction::SkipIfZero(r) => (r, Condition::Equal, Value::Uint(0), pc + 2),
+        Instruction::SkipIfNonZero(r) => (r, Condition::NotEqual, Value::Uint(0), pc + 2),
+        Instruction::JumpIfFlag(condition, a) => {
+            return Op::JumpFlag {
+                condition,
+                target: usize::from(a),
+            }
+        }
+        _ => return Op::Instruction(*instruction),
+    };
+
+    match register {
+        Register::UintRegister(r) => Op::JumpUint {
+            register: r as usize,
+            condition,
+            value,
+            target,
+        },
+        Register::FloatRegister(r) => Op::JumpFloat {
+            register: r as usize,
+            condition,
+            value,
+            target,
+        },
+    }
+}
@@ -1,6 +1,16 @@
-use std::convert::TryFrom;
+use core::cmp::{Ordering, Reverse};
+use core::convert::TryFrom;
+use core::fmt;
 
-use crate::Opcode;
+#[cfg(not(feature = "std"))]
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+use crate::{Opcode, OperandKind};
 
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum UintRegister {
@@ -35,9 +45,31 @@ pub enum Register {
     FloatRegister(FloatRegister),
 }
 
+impl From<UintRegister> for Register {
+    fn from(r: UintRegister) -> Self {
+        Register::UintRegister(r)
+    }
+}
+
+impl From<FloatRegister> for Register {
+    fn from(r: FloatRegister) -> Self {
+        Register::FloatRegister(r)
+    }
+}
+
 impl Register {
-    fn from_u8(r: u8) -> Self {
-        match r {
+    /// The byte naming this register in bytecode, the inverse of [`Register::from_u8`].
+    pub(crate) fn to_u8(self) -> u8 {
+        match self {
+            Register::UintRegister(r) => r as u8,
+            Register::FloatRegister(r) => 0x8 + r as u8,
+        }
+    }
+
+    /// The register named by a byte in bytecode, the inverse of
+    /// [`register_from_str`](crate::register_from_str).
+    pub fn from_u8(r: u8) -> Option<Self> {
+        let register = match r {
             0x0 => Register::UintRegister(UintRegister::A),
             0x1 => Register::UintRegister(UintRegister::B),
             0x2 => Register::UintRegister(UintRegister::C),
@@ -54,7 +86,17 @@ impl Register {
             0xd => Register::FloatRegister(FloatRegister::X),
             0xe => Register::FloatRegister(FloatRegister::Y),
             0xf => Register::FloatRegister(FloatRegister::Z),
-            _ => panic!("invalid register: {}", r),
+            _ => return None,
+        };
+        Some(register)
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Register::UintRegister(r) => write!(f, "{:?}", r),
+            Register::FloatRegister(r) => write!(f, "{:?}", r),
         }
     }
 }
@@ -62,12 +104,195 @@ impl Register {
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum Value {
     Uint(u16),
+    /// Encoded as a register value whose register byte is [`FLOAT_IMMEDIATE`], followed by the
+    /// eight byte `f64`
     Float(f64),
     Register(Register),
 }
 
+/// In place of a register number, marks a value as an `f64` immediate.
+pub const FLOAT_IMMEDIATE: u8 = 0xff;
+
+/// How `FWD` interprets the angle in register `A`. Since `A` is a uint register the finer units
+/// are fixed point.
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
+pub enum AngleUnit {
+    /// Whole degrees
+    #[default]
+    Degrees = 0,
+    /// Thousandths of a radian
+    Radians = 1,
+    /// 65536ths of a turn, so the full range of `A` is one revolution
+    Turns = 2,
+}
+
+impl AngleUnit {
+    /// The name used for this unit in assembly.
+    pub fn name(self) -> &'static str {
+        match self {
+            AngleUnit::Degrees => "DEG",
+            AngleUnit::Radians => "RAD",
+            AngleUnit::Turns => "TURN",
+        }
+    }
+
+    fn from_u8(unit: u8) -> Option<Self> {
+        match unit {
+            0x0 => Some(AngleUnit::Degrees),
+            0x1 => Some(AngleUnit::Radians),
+            0x2 => Some(AngleUnit::Turns),
+            _ => None,
+        }
+    }
+}
+
+/// What a conditional jump checks for, given how one value compared to another.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Condition {
+    Equal = 0,
+    NotEqual = 1,
+    GreaterThan = 2,
+    LessThan = 3,
+}
+
+impl Condition {
+    /// The name used for this condition in assembly.
+    pub fn name(self) -> &'static str {
+        match self {
+            Condition::Equal => "EQ",
+            Condition::NotEqual => "NE",
+            Condition::GreaterThan => "GT",
+            Condition::LessThan => "LT",
+        }
+    }
+
+    /// Whether a comparison with this `ordering` meets the condition. Comparisons without an
+    /// ordering, like those with NaN, meet none.
+    pub(crate) fn holds(self, ordering: Option<Ordering>) -> bool {
+        match (self, ordering) {
+            (_, None) => false,
+            (Condition::Equal, Some(ordering)) => ordering == Ordering::Equal,
+            (Condition::NotEqual, Some(ordering)) => ordering != Ordering::Equal,
+            (Condition::GreaterThan, Some(ordering)) => ordering == Ordering::Greater,
+            (Condition::LessThan, Some(ordering)) => ordering == Ordering::Less,
+        }
+    }
+
+    fn from_u8(condition: u8) -> Option<Self> {
+        match condition {
+            0x0 => Some(Condition::Equal),
+            0x1 => Some(Condition::NotEqual),
+            0x2 => Some(Condition::GreaterThan),
+            0x3 => Some(Condition::LessThan),
+            _ => None,
+        }
+    }
+}
+
+/// The byte order of multi-byte values in a program.
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
+pub enum Endianness {
+    #[default]
+    Little = 0,
+    Big = 1,
+}
+
+impl Endianness {
+    pub fn name(self) -> &'static str {
+        match self {
+            Endianness::Little => "little",
+            Endianness::Big => "big",
+        }
+    }
+
+    pub fn u16_to_bytes(self, value: u16) -> [u8; 2] {
+        match self {
+            Endianness::Little => value.to_le_bytes(),
+            Endianness::Big => value.to_be_bytes(),
+        }
+    }
+
+    pub fn u32_to_bytes(self, value: u32) -> [u8; 4] {
+        match self {
+            Endianness::Little => value.to_le_bytes(),
+            Endianness::Big => value.to_be_bytes(),
+        }
+    }
+
+    pub fn f64_to_bytes(self, value: f64) -> [u8; 8] {
+        match self {
+            Endianness::Little => value.to_le_bytes(),
+            Endianness::Big => value.to_be_bytes(),
+        }
+    }
+
+    fn u16_from_bytes(self, bytes: [u8; 2]) -> u16 {
+        match self {
+            Endianness::Little => u16::from_le_bytes(bytes),
+            Endianness::Big => u16::from_be_bytes(bytes),
+        }
+    }
+
+    fn u32_from_bytes(self, bytes: [u8; 4]) -> u32 {
+        match self {
+            Endianness::Little => u32::from_le_bytes(bytes),
+            Endianness::Big => u32::from_be_bytes(bytes),
+        }
+    }
+
+    fn f64_from_bytes(self, bytes: [u8; 8]) -> f64 {
+        match self {
+            Endianness::Little => f64::from_le_bytes(bytes),
+            Endianness::Big => f64::from_be_bytes(bytes),
+        }
+    }
+
+    fn from_u8(endianness: u8) -> Option<Self> {
+        match endianness {
+            0x0 => Some(Endianness::Little),
+            0x1 => Some(Endianness::Big),
+            _ => None,
+        }
+    }
+}
+
+impl From<u16> for Value {
+    fn from(n: u16) -> Self {
+        Value::Uint(n)
+    }
+}
+
+impl From<Register> for Value {
+    fn from(r: Register) -> Self {
+        Value::Register(r)
+    }
+}
+
+impl From<UintRegister> for Value {
+    fn from(r: UintRegister) -> Self {
+        Value::Register(r.into())
+    }
+}
+
+impl From<FloatRegister> for Value {
+    fn from(r: FloatRegister) -> Self {
+        Value::Register(r.into())
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Uint(n) => write!(f, "{}", n),
+            // Always with a decimal point, so it assembles back to a float
+            Value::Float(n) => write!(f, "{:?}", n),
+            Value::Register(r) => write!(f, "{}", r),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Copy, Clone)]
-pub struct Address(u16);
+pub struct Address(pub(crate) u32);
 
 impl From<Address> for usize {
     fn from(addr: Address) -> Self {
@@ -75,11 +300,47 @@ impl From<Address> for usize {
     }
 }
 
-/// Instructions are ...
+impl From<u16> for Address {
+    fn from(addr: u16) -> Self {
+        Address(addr.into())
+    }
+}
+
+/// A single operand of an instruction, of the kind given by [`Opcode::operand_kinds`].
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum Operand {
+    Register(Register),
+    Value(Value),
+    Address(Address),
+    AngleUnit(AngleUnit),
+    Condition(Condition),
+}
+
+impl Operand {
+    fn kind(self) -> OperandKind {
+        match self {
+            Operand::Register(_) => OperandKind::Register,
+            Operand::Value(_) => OperandKind::Value,
+            Operand::Address(_) => OperandKind::Address,
+            Operand::AngleUnit(_) => OperandKind::AngleUnit,
+            Operand::Condition(_) => OperandKind::Condition,
+        }
+    }
+}
+
+/// Instructions are encoded as an opcode byte followed by their operands. Registers take one byte,
+/// immediate values and addresses two little endian bytes. The high bit of the opcode byte marks
+/// the last value operand as a register, and for instructions taking two values the next bit marks
+/// the first. A marked value whose register byte is [`FLOAT_IMMEDIATE`] is an eight byte `f64`
+/// instead.
 ///
 /// ```text
+/// STO A 5      03 00 05 00
+/// STO A B      83 00 01
+/// ARC C 90     57 02 5a 00
+/// STO S 0.5    83 08 ff 00 00 00 00 00 00 e0 3f
 /// ```
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Copy, Clone)]
 pub enum Instruction {
     /// Toggle if we're drawing or not
     ///
@@ -100,6 +361,13 @@ pub enum Instruction {
     /// FWD
     /// ```
     Forward,
+    /// Update float registers `X` and `Y` to move by `Rx` across and `Ry` down, whatever the
+    /// angle in `A`. Like `FWD`, this draws if the pen is down.
+    ///
+    /// ```text
+    /// MOVE Rx Ry
+    /// ```
+    MoveBy(Register, Register),
     /// Set the register `Rx` to the product of `Rx` and either the immediate value `n`, or the
     /// value in the register `Ry`.
     ///
@@ -134,6 +402,10 @@ pub enum Instruction {
     Sub(Register, Value),
     /// Set the register `Rx` to either the immediate value `n`, or the value in the register `Ry`.
     ///
+    /// Values of the other kind are converted, as they are wherever a value is used. Floats
+    /// become uints rounded toward zero and clamped to `0..=65535`, with NaN becoming 0. Uints
+    /// become the equal float.
+    ///
     /// ```text
     /// STO Rx n
     /// STO Rx Ry
@@ -189,51 +461,848 @@ pub enum Instruction {
     /// JLT Rx Ry label:
     /// ```
     JumpIfLessThan(Register, Value, Address),
+    /// Color drawn pixels with a spectrum that cycles every `n` (or `Ry`) cycles instead of the
+    /// solid pen color. A period of `0` turns the gradient off again.
+    ///
+    /// ```text
+    /// GRAD n
+    /// GRAD Ry
+    /// ```
+    Gradient(Value),
+    /// Set the register `Rx` to the smaller of `Rx` and either the immediate value `n`, or the
+    /// value in the register `Ry`.
+    ///
+    /// ```text
+    /// MIN Rx n
+    /// MIN Rx Ry
+    /// ```
+    Min(Register, Value),
+    /// Set the register `Rx` to the larger of `Rx` and either the immediate value `n`, or the
+    /// value in the register `Ry`.
+    ///
+    /// ```text
+    /// MAX Rx n
+    /// MAX Rx Ry
+    /// ```
+    Max(Register, Value),
+    /// Set the register `Rx` to its absolute value. Uint registers are unsigned so this leaves
+    /// them unchanged.
+    ///
+    /// ```text
+    /// ABS Rx
+    /// ```
+    Abs(Register),
+    /// Set the unit `FWD` reads the angle in register `A` as. Defaults to `DEG`.
+    ///
+    /// ```text
+    /// AUNIT DEG
+    /// AUNIT RAD
+    /// AUNIT TURN
+    /// ```
+    AngleUnit(AngleUnit),
+    /// Push the value of register `Rx` onto the data stack.
+    ///
+    /// ```text
+    /// PUSH Rx
+    /// ```
+    Push(Register),
+    /// Pop the top of the data stack into register `Rx`, converting it if it was pushed from a
+    /// register of the other kind.
+    ///
+    /// ```text
+    /// POP Rx
+    /// ```
+    Pop(Register),
+    /// Draw an arc of radius `r` (or `Rx`) around the current position, starting at the current
+    /// angle and sweeping through `n` (or `Ry`) degrees. The position and angle are unchanged, and
+    /// nothing is drawn unless drawing is toggled on. An infinite or NaN size draws nothing, and
+    /// at most [`MAX_SHAPE_POINTS`](crate::vm::MAX_SHAPE_POINTS) points are drawn.
+    ///
+    /// ```text
+    /// ARC r n
+    /// ARC r Ry
+    /// ARC Rx n
+    /// ARC Rx Ry
+    /// ```
+    Arc(Value, Value),
+    /// Set the register `Rx` to the value of the register numbered by the value in `Ri`, where
+    /// `A`-`H` are 0-7 and `S`-`Z` are 8-15.
+    ///
+    /// ```text
+    /// LDI Rx Ri
+    /// ```
+    LoadIndirect(Register, Register),
+    /// Set the register numbered by the value in `Ri` to the value in `Rx`, where `A`-`H` are 0-7
+    /// and `S`-`Z` are 8-15.
+    ///
+    /// ```text
+    /// STI Ri Rx
+    /// ```
+    StoreIndirect(Register, Register),
+    /// Set the register `Rx` to the word in memory at the address in `Ra`.
+    ///
+    /// ```text
+    /// LOAD Ra Rx
+    /// ```
+    Load(Register, Register),
+    /// Set the word in memory at the address in `Ra` to the value in `Rx`.
+    ///
+    /// ```text
+    /// SAVE Ra Rx
+    /// ```
+    Save(Register, Register),
+    /// Flood fill the area around the current position that's the same color as it with the
+    /// current color.
+    ///
+    /// ```text
+    /// FILL
+    /// ```
+    Fill,
+    /// Move back to the origin and face the starting direction, setting `X`, `Y` and `A` to 0.
+    ///
+    /// ```text
+    /// HOME
+    /// ```
+    Home,
+    /// Compare the register `Rx` with the immediate value `n`, or the value in `Ry`, remembering
+    /// the result for `JF`.
+    ///
+    /// ```text
+    /// CMP Rx n
+    /// CMP Rx Ry
+    /// ```
+    Compare(Register, Value),
+    /// Jump to `label:` if the last `CMP` met the condition, one of `EQ`, `NE`, `GT` or `LT`.
+    ///
+    /// ```text
+    /// JF EQ label:
+    /// ```
+    JumpIfFlag(Condition, Address),
+    /// Draw a pixel at the current position, whether or not drawing is toggled on.
+    ///
+    /// ```text
+    /// PLOT
+    /// ```
+    Plot,
+    /// Print the registers, program counter and cycle count to STDERR, if the VM has debugging
+    /// enabled.
+    ///
+    /// ```text
+    /// DBG
+    /// ```
+    Debug,
+    /// Draw with the palette color at index `n`, or the index in `Ry`, turning off any `GRAD`
+    /// gradient.
+    ///
+    /// ```text
+    /// COLI n
+    /// COLI Ry
+    /// ```
+    ColorIndex(Value),
+    /// Draw dots `n` pixels across, or the width in `Ry`. Widths of 0 and 1 draw single pixels,
+    /// and widths past [`MAX_DIMENSION`] are capped to it.
+    ///
+    /// ```text
+    /// PW n
+    /// PW Ry
+    /// ```
+    PenWidth(Value),
+    /// Copy the number of instructions executed so far, including this one, into `Rx`. Uint
+    /// registers wrap around at 65536.
+    ///
+    /// ```text
+    /// TIME Rx
+    /// ```
+    Time(Register),
+    /// Skip the next instruction if `Rx` is zero.
+    ///
+    /// ```text
+    /// SKZ Rx
+    /// ```
+    SkipIfZero(Register),
+    /// Skip the next instruction if `Rx` isn't zero.
+    ///
+    /// ```text
+    /// SKNZ Rx
+    /// ```
+    SkipIfNonZero(Register),
+    /// Draw dashed lines, alternating `on` (or `Rx`) drawn pixels with `off` (or `Ry`) skipped
+    /// ones. `DASH 0 0` goes back to solid lines.
+    ///
+    /// ```text
+    /// DASH on off
+    /// DASH on Ry
+    /// DASH Rx off
+    /// DASH Rx Ry
+    /// ```
+    Dash(Value, Value),
+    /// Point the turtle along the direction `Rx`, `Ry` by setting `A` to its angle in the current
+    /// angle unit.
+    ///
+    /// ```text
+    /// HEADING Rx Ry
+    /// ```
+    Heading(Register, Register),
+    /// Set the pen color from red, green and blue channels in `Rr`, `Rg` and `Rb`, each taken
+    /// modulo 256.
+    ///
+    /// ```text
+    /// RGB Rr Rg Rb
+    /// ```
+    Rgb(Register, Register, Register),
+    /// Switch to turtle `n`, or the turtle numbered by `Ry`, of [`TURTLES`](crate::vm::TURTLES).
+    /// Each turtle has its own `A`, `X` and `Y`, pen and color, which are saved when switching
+    /// away from it and restored when switching back. All other registers are shared. Programs
+    /// start on turtle 0.
+    ///
+    /// ```text
+    /// SEL n
+    /// SEL Ry
+    /// ```
+    Select(Value),
+    /// Jump to the instruction index in `Rx`, as loaded with the assembler's `LEA label: Rx`.
+    ///
+    /// ```text
+    /// JMPR Rx
+    /// ```
+    JumpRegister(Register),
+    /// Repeat everything drawn `n` (or `Rx`) ways around the origin, each copy rotated a further
+    /// `1/n` of a turn. When `reflect` (or `Ry`) is non-zero each copy is also drawn reflected
+    /// across the X axis. `MIRROR 0 0` or `MIRROR 1 0` draws normally again.
+    ///
+    /// ```text
+    /// MIRROR n reflect
+    /// MIRROR n Ry
+    /// MIRROR Rx reflect
+    /// MIRROR Rx Ry
+    /// ```
+    Mirror(Value, Value),
+    /// Ask the renderer to pause for `n` (or `Ry`) milliseconds before drawing anything more. The
+    /// VM itself doesn't wait.
+    ///
+    /// ```text
+    /// WAIT n
+    /// WAIT Ry
+    /// ```
+    Wait(Value),
+    /// Draw a regular polygon of `n` (or `Rx`) sides, each `len` (or `Ry`) long, starting at the
+    /// current position and angle and turning a further `1/n` of a turn at each corner. The
+    /// position and angle are unchanged, and nothing is drawn unless drawing is toggled on. An
+    /// infinite or NaN length draws nothing, and at most
+    /// [`MAX_SHAPE_POINTS`](crate::vm::MAX_SHAPE_POINTS) points are drawn.
+    ///
+    /// ```text
+    /// POLY n len
+    /// POLY n Ry
+    /// POLY Rx len
+    /// POLY Rx Ry
+    /// ```
+    Poly(Value, Value),
+    /// Draw a spiral of `n` (or `Rx`) turns out from the current position, starting at the
+    /// current angle and growing `growth` (or `Ry`) further from the center with each turn. The
+    /// position and angle are unchanged, and nothing is drawn unless drawing is toggled on. An
+    /// infinite or NaN growth draws nothing, and the spiral stops after
+    /// [`MAX_SHAPE_POINTS`](crate::vm::MAX_SHAPE_POINTS) points.
+    ///
+    /// ```text
+    /// SPIRAL n growth
+    /// SPIRAL n Ry
+    /// SPIRAL Rx growth
+    /// SPIRAL Rx Ry
+    /// ```
+    Spiral(Value, Value),
+    /// Set the register `Rx` to the value in `Ry`, rounding floats to the nearest whole number,
+    /// halfway cases away from zero. Floats stored in a uint register are then clamped to
+    /// `0..=65535` as with `STO`, which rounds toward zero instead.
+    ///
+    /// ```text
+    /// CVT Rx Ry
+    /// ```
+    Convert(Register, Register),
+    /// Copy the color of the pixel under the turtle into `Rx`, as it's been drawn on a canvas the
+    /// program's size with 0,0 in the center, whatever the renderer's options. Float registers get
+    /// `0xRRGGBB`, and uint registers, which can't hold that, get the color as RGB565: five bits of
+    /// red, six of green and five of blue. Outside the canvas is the background color.
+    ///
+    /// ```text
+    /// SAMPLE Rx
+    /// ```
+    Sample(Register),
+}
+
+impl Instruction {
+    /// The instruction index a jump goes to when taken.
+    pub fn jump_target(&self) -> Option<usize> {
+        use Instruction::*;
+
+        match *self {
+            JumpIfNonZero(_, a)
+            | JumpIfEqual(_, _, a)
+            | JumpIfNotEqual(_, _, a)
+            | JumpIfGreaterThan(_, _, a)
+            | JumpIfLessThan(_, _, a)
+            | JumpIfFlag(_, a) => Some(a.into()),
+            _ => None,
+        }
+    }
+
+    /// Point a jump at `addr`. Does nothing for other instructions.
+    pub(crate) fn set_jump_target(&mut self, addr: Address) {
+        use Instruction::*;
+
+        match self {
+            JumpIfNonZero(_, a)
+            | JumpIfEqual(_, _, a)
+            | JumpIfNotEqual(_, _, a)
+            | JumpIfGreaterThan(_, _, a)
+            | JumpIfLessThan(_, _, a)
+            | JumpIfFlag(_, a) => *a = addr,
+            _ => {}
+        }
+    }
+
+    /// The instruction's operands in the order they're encoded.
+    fn operands(&self) -> Vec<Operand> {
+        use Instruction::*;
+        use Operand as O;
+
+        match *self {
+            Draw | Halt | Forward | Fill | Home | Plot | Debug => vec![],
+            Increment(r) | Decrement(r) | Abs(r) | Push(r) | Pop(r) | Time(r) | SkipIfZero(r)
+            | SkipIfNonZero(r) | JumpRegister(r) | Sample(r) => vec![O::Register(r)],
+            Multiply(r, v)
+            | Divide(r, v)
+            | Add(r, v)
+            | Sub(r, v)
+            | Store(r, v)
+            | Min(r, v)
+            | Max(r, v)
+            | Compare(r, v) => vec![O::Register(r), O::Value(v)],
+            JumpIfNonZero(r, a) => vec![O::Register(r), O::Address(a)],
+            JumpIfEqual(r, v, a)
+            | JumpIfNotEqual(r, v, a)
+            | JumpIfGreaterThan(r, v, a)
+            | JumpIfLessThan(r, v, a) => vec![O::Register(r), O::Value(v), O::Address(a)],
+            Gradient(v) | ColorIndex(v) | PenWidth(v) | Select(v) | Wait(v) => vec![O::Value(v)],
+            AngleUnit(unit) => vec![O::AngleUnit(unit)],
+            JumpIfFlag(condition, a) => vec![O::Condition(condition), O::Address(a)],
+            Arc(v1, v2) | Dash(v1, v2) | Mirror(v1, v2) | Poly(v1, v2) | Spiral(v1, v2) => {
+                vec![O::Value(v1), O::Value(v2)]
+            }
+            LoadIndirect(r1, r2)
+            | StoreIndirect(r1, r2)
+            | Load(r1, r2)
+            | Save(r1, r2)
+            | Heading(r1, r2)
+            | MoveBy(r1, r2)
+            | Convert(r1, r2) => vec![O::Register(r1), O::Register(r2)],
+            Rgb(r, g, b) => vec![O::Register(r), O::Register(g), O::Register(b)],
+        }
+    }
+
+    /// The registers the instruction's operands read. Moves also read `A`, `X` and `Y`, and `LDI`
+    /// the register its index names, which aren't included.
+    pub(crate) fn reads(&self) -> Vec<Register> {
+        use Instruction::*;
+
+        // The index of a register operand that's written without being read first
+        let write_only = match *self {
+            Store(..) | Pop(_) | Time(_) | LoadIndirect(..) | Convert(..) | Sample(_) => Some(0),
+            Load(..) => Some(1),
+            _ => None,
+        };
+
+        self.operands()
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, operand)| match operand {
+                Operand::Register(r) if Some(i) != write_only => Some(r),
+                Operand::Value(Value::Register(r)) => Some(r),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The register the instruction's operands write. `STI` writes the register its index names,
+    /// which isn't included.
+    pub(crate) fn writes(&self) -> Option<Register> {
+        use Instruction::*;
+
+        match *self {
+            Multiply(r, _)
+            | Divide(r, _)
+            | Add(r, _)
+            | Sub(r, _)
+            | Store(r, _)
+            | Min(r, _)
+            | Max(r, _)
+            | Increment(r)
+            | Decrement(r)
+            | Abs(r)
+            | Pop(r)
+            | Time(r)
+            | LoadIndirect(r, _)
+            | Convert(r, _)
+            | Sample(r)
+            | Load(_, r) => Some(r),
+            _ => None,
+        }
+    }
+
+    /// Whether this instruction can skip the one after it.
+    pub fn is_skip(&self) -> bool {
+        matches!(
+            self,
+            Instruction::SkipIfZero(_) | Instruction::SkipIfNonZero(_)
+        )
+    }
+
+    pub fn opcode(&self) -> Opcode {
+        use Instruction::*;
+
+        match self {
+            Draw => Opcode::DRW,
+            Halt => Opcode::HLT,
+            Forward => Opcode::FWD,
+            Multiply(..) => Opcode::MUL,
+            Divide(..) => Opcode::DIV,
+            Add(..) => Opcode::ADD,
+            Sub(..) => Opcode::SUB,
+            Store(..) => Opcode::STO,
+            Decrement(..) => Opcode::DEC,
+            Increment(..) => Opcode::INC,
+            JumpIfNonZero(..) => Opcode::JNZ,
+            JumpIfEqual(..) => Opcode::JEQ,
+            JumpIfNotEqual(..) => Opcode::JNE,
+            JumpIfGreaterThan(..) => Opcode::JGT,
+            JumpIfLessThan(..) => Opcode::JLT,
+            Gradient(..) => Opcode::GRAD,
+            Min(..) => Opcode::MIN,
+            Max(..) => Opcode::MAX,
+            Abs(..) => Opcode::ABS,
+            AngleUnit(..) => Opcode::AUNIT,
+            Push(..) => Opcode::PUSH,
+            Pop(..) => Opcode::POP,
+            Arc(..) => Opcode::ARC,
+            LoadIndirect(..) => Opcode::LDI,
+            StoreIndirect(..) => Opcode::STI,
+            Load(..) => Opcode::LOAD,
+            Save(..) => Opcode::SAVE,
+            Fill => Opcode::FILL,
+            Home => Opcode::HOME,
+            Compare(..) => Opcode::CMP,
+            JumpIfFlag(..) => Opcode::JF,
+            Plot => Opcode::PLOT,
+            Debug => Opcode::DBG,
+            ColorIndex(..) => Opcode::COLI,
+            PenWidth(..) => Opcode::PW,
+            Select(..) => Opcode::SEL,
+            JumpRegister(..) => Opcode::JMPR,
+            Time(..) => Opcode::TIME,
+            SkipIfZero(..) => Opcode::SKZ,
+            SkipIfNonZero(..) => Opcode::SKNZ,
+            Dash(..) => Opcode::DASH,
+            Mirror(..) => Opcode::MIRROR,
+            Wait(..) => Opcode::WAIT,
+            Poly(..) => Opcode::POLY,
+            Spiral(..) => Opcode::SPIRAL,
+            Heading(..) => Opcode::HEADING,
+            MoveBy(..) => Opcode::MOVE,
+            Convert(..) => Opcode::CVT,
+            Sample(..) => Opcode::SAMPLE,
+            Rgb(..) => Opcode::RGB,
+        }
+    }
+
+    /// Serialize as a JSON object, e.g. `{"op":"STO","reg":"A","value":5}`. Register operands are
+    /// written as their name and jump targets as an instruction index under `addr`.
+    pub fn to_json(&self) -> String {
+        use Instruction::*;
+
+        let reg = |r: Register| ("reg", format!("\"{}\"", r));
+        let value = |v: Value| {
+            let v = match v {
+                Value::Uint(n) => n.to_string(),
+                Value::Float(n) if n.is_finite() => n.to_string(),
+                Value::Float(_) => "null".to_string(),
+                Value::Register(r) => format!("\"{}\"", r),
+            };
+            ("value", v)
+        };
+        let addr = |a: Address| ("addr", usize::from(a).to_string());
+
+        let mut fields = vec![("op", format!("\"{}\"", self.opcode().mnemonic()))];
+        match *self {
+            Draw | Halt | Forward | Fill | Home | Plot | Debug => {}
+            Increment(r) | Decrement(r) | Abs(r) | Push(r) | Pop(r) | Time(r) | SkipIfZero(r)
+            | SkipIfNonZero(r) | JumpRegister(r) | Sample(r) => fields.push(reg(r)),
+            Multiply(r, v)
+            | Divide(r, v)
+            | Add(r, v)
+            | Sub(r, v)
+            | Store(r, v)
+            | Min(r, v)
+            | Max(r, v)
+            | Compare(r, v) => fields.extend(vec![reg(r), value(v)]),
+            JumpIfNonZero(r, a) => fields.extend(vec![reg(r), addr(a)]),
+            JumpIfEqual(r, v, a)
+            | JumpIfNotEqual(r, v, a)
+            | JumpIfGreaterThan(r, v, a)
+            | JumpIfLessThan(r, v, a) => fields.extend(vec![reg(r), value(v), addr(a)]),
+            Gradient(v) | ColorIndex(v) | PenWidth(v) | Select(v) | Wait(v) => {
+                fields.push(value(v))
+            }
+            AngleUnit(unit) => fields.push(("unit", format!("\"{}\"", unit.name()))),
+            JumpIfFlag(condition, a) => fields.extend(vec![
+                ("condition", format!("\"{}\"", condition.name())),
+                addr(a),
+            ]),
+            Arc(radius, sweep) => {
+                fields.extend(vec![("radius", value(radius).1), ("sweep", value(sweep).1)])
+            }
+            Dash(on, off) => fields.extend(vec![("on", value(on).1), ("off", value(off).1)]),
+            Mirror(ways, reflect) => {
+                fields.extend(vec![("ways", value(ways).1), ("reflect", value(reflect).1)])
+            }
+            Poly(sides, length) => {
+                fields.extend(vec![("sides", value(sides).1), ("length", value(length).1)])
+            }
+            Spiral(turns, growth) => {
+                fields.extend(vec![("turns", value(turns).1), ("growth", value(growth).1)])
+            }
+            LoadIndirect(r, index) => fields.extend(vec![reg(r), ("index", reg(index).1)]),
+            StoreIndirect(index, r) => fields.extend(vec![("index", reg(index).1), reg(r)]),
+            Load(addr, r) | Save(addr, r) => fields.extend(vec![("addr", reg(addr).1), reg(r)]),
+            Heading(x, y) => fields.extend(vec![("x", reg(x).1), ("y", reg(y).1)]),
+            MoveBy(dx, dy) => fields.extend(vec![("dx", reg(dx).1), ("dy", reg(dy).1)]),
+            Convert(r, from) => fields.extend(vec![reg(r), ("from", reg(from).1)]),
+            Rgb(r, g, b) => fields.extend(vec![
+                ("red", reg(r).1),
+                ("green", reg(g).1),
+                ("blue", reg(b).1),
+            ]),
+        }
+
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|(key, value)| format!("\"{}\":{}", key, value))
+            .collect();
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+/// Disassemble into the assembler's syntax. Jump targets are shown as instruction indices since
+/// label names aren't kept in the bytecode.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Instruction::*;
+
+        let mnemonic = self.opcode().mnemonic();
+        match *self {
+            Draw | Halt | Forward | Fill | Home | Plot | Debug => write!(f, "{}", mnemonic),
+            Increment(r) | Decrement(r) | Abs(r) | Push(r) | Pop(r) | Time(r) | SkipIfZero(r)
+            | SkipIfNonZero(r) | JumpRegister(r) | Sample(r) => {
+                write!(f, "{} {}", mnemonic, r)
+            }
+            Multiply(r, v)
+            | Divide(r, v)
+            | Add(r, v)
+            | Sub(r, v)
+            | Store(r, v)
+            | Min(r, v)
+            | Max(r, v)
+            | Compare(r, v) => write!(f, "{} {} {}", mnemonic, r, v),
+            JumpIfNonZero(r, a) => write!(f, "{} {} {}", mnemonic, r, usize::from(a)),
+            JumpIfEqual(r, v, a)
+            | JumpIfNotEqual(r, v, a)
+            | JumpIfGreaterThan(r, v, a)
+            | JumpIfLessThan(r, v, a) => {
+                write!(f, "{} {} {} {}", mnemonic, r, v, usize::from(a))
+            }
+            Gradient(v) | ColorIndex(v) | PenWidth(v) | Select(v) | Wait(v) => {
+                write!(f, "{} {}", mnemonic, v)
+            }
+            AngleUnit(unit) => write!(f, "{} {}", mnemonic, unit.name()),
+            JumpIfFlag(condition, a) => {
+                write!(f, "{} {} {}", mnemonic, condition.name(), usize::from(a))
+            }
+            Arc(v1, v2) | Dash(v1, v2) | Mirror(v1, v2) | Poly(v1, v2) | Spiral(v1, v2) => {
+                write!(f, "{} {} {}", mnemonic, v1, v2)
+            }
+            LoadIndirect(r1, r2)
+            | StoreIndirect(r1, r2)
+            | Load(r1, r2)
+            | Save(r1, r2)
+            | Heading(r1, r2)
+            | MoveBy(r1, r2)
+            | Convert(r1, r2) => write!(f, "{} {} {}", mnemonic, r1, r2),
+            Rgb(r, g, b) => write!(f, "{} {} {} {}", mnemonic, r, g, b),
+        }
+    }
+}
+
+/// Maps instruction indices (the VM's program counter) back to the source line they were
+/// assembled from, as written by `assembler --map`.
+#[derive(Debug, PartialEq)]
+pub struct SourceMap {
+    lines: Vec<usize>,
+}
+
+impl SourceMap {
+    /// Parse the `<instruction index> <line number>` pairs written by the assembler.
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut lines = Vec::new();
+
+        for entry in input.lines() {
+            let mut parts = entry.split_whitespace();
+            let (index, line) = match (parts.next(), parts.next()) {
+                (Some(index), Some(line)) => (index, line),
+                _ => return Err(format!("invalid source map entry: {}", entry)),
+            };
+
+            let index: usize = index
+                .parse()
+                .map_err(|_| format!("invalid instruction index: {}", index))?;
+            let line = line
+                .parse()
+                .map_err(|_| format!("invalid line number: {}", line))?;
+
+            if index != lines.len() {
+                return Err(format!("out of order instruction index: {}", index));
+            }
+            lines.push(line);
+        }
+
+        Ok(SourceMap { lines })
+    }
+
+    /// The source line of the instruction at `pc`.
+    pub fn line(&self, pc: usize) -> Option<usize> {
+        self.lines.get(pc).copied()
+    }
+}
+
+/// Returned when a buffer isn't a valid program.
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    /// The buffer ended part way through the header or an instruction
+    UnexpectedEnd,
+    UnsupportedVersion(u8),
+    InvalidOpcode {
+        opcode: u8,
+        offset: usize,
+    },
+    InvalidRegister {
+        register: u8,
+        offset: usize,
+    },
+    InvalidAngleUnit {
+        unit: u8,
+        offset: usize,
+    },
+    InvalidCondition {
+        condition: u8,
+        offset: usize,
+    },
+    InvalidEndianness(u8),
+    PaletteTooLarge(u8),
+    /// The width or height is zero or larger than [`MAX_DIMENSION`]
+    InvalidSize {
+        width: u16,
+        height: u16,
+    },
+    /// The header sets flags this version doesn't know about
+    UnknownFlags(u8),
+    /// A varint immediate runs past three bytes or doesn't fit in a `u16`
+    InvalidVarint {
+        offset: usize,
+    },
+    /// The embedded source isn't UTF-8
+    InvalidSource,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of program"),
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported version: {}", version)
+            }
+            DecodeError::InvalidOpcode { opcode, offset } => {
+                write!(f, "invalid opcode {:#04x} at byte {}", opcode, offset)
+            }
+            DecodeError::InvalidRegister { register, offset } => {
+                write!(f, "invalid register {} at byte {}", register, offset)
+            }
+            DecodeError::InvalidAngleUnit { unit, offset } => {
+                write!(f, "invalid angle unit {} at byte {}", unit, offset)
+            }
+            DecodeError::InvalidCondition { condition, offset } => {
+                write!(f, "invalid condition {} at byte {}", condition, offset)
+            }
+            DecodeError::InvalidEndianness(endianness) => {
+                write!(f, "invalid endianness: {}", endianness)
+            }
+            DecodeError::PaletteTooLarge(len) => {
+                write!(
+                    f,
+                    "palette has {} colors, at most {} are allowed",
+                    len, PALETTE_SIZE
+                )
+            }
+            DecodeError::InvalidSize { width, height } => write!(
+                f,
+                "invalid size {}x{}, each side must be from 1 to {}",
+                width, height, MAX_DIMENSION
+            ),
+            DecodeError::UnknownFlags(flags) => write!(f, "unknown header flags: {:#04x}", flags),
+            DecodeError::InvalidVarint { offset } => {
+                write!(f, "invalid varint at byte {}", offset)
+            }
+            DecodeError::InvalidSource => write!(f, "embedded source isn't valid UTF-8"),
+        }
+    }
 }
 
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {}
+
 struct Program<'a> {
     buffer: &'a [u8],
     cursor: usize,
+    endianness: Endianness,
+    /// Whether immediate values are varints, from the header
+    varint: bool,
+    /// Whether addresses are four bytes rather than two, from the header
+    wide_addresses: bool,
 }
 
 impl<'a> Program<'a> {
-    fn read_u8(&mut self) -> u8 {
-        let item = self.buffer[self.cursor];
+    fn is_empty(&self) -> bool {
+        self.cursor >= self.buffer.len()
+    }
+
+    /// Split the source section off the end of the buffer, so reading instructions stops before
+    /// it.
+    fn take_source(&mut self) -> Result<String, DecodeError> {
+        let buffer = self.buffer;
+        let len_at = buffer
+            .len()
+            .checked_sub(4)
+            .filter(|&at| at >= self.cursor)
+            .ok_or(DecodeError::UnexpectedEnd)?;
+        let mut len = [0; 4];
+        len.copy_from_slice(&buffer[len_at..]);
+        let len = self.endianness.u32_from_bytes(len) as usize;
+        let start = len_at
+            .checked_sub(len)
+            .filter(|&start| start >= self.cursor)
+            .ok_or(DecodeError::UnexpectedEnd)?;
+
+        let source =
+            core::str::from_utf8(&buffer[start..len_at]).map_err(|_| DecodeError::InvalidSource)?;
+        self.buffer = &buffer[..start];
+        Ok(source.to_string())
+    }
+
+    fn read_u8(&mut self) -> Result<u8, DecodeError> {
+        let item = *self
+            .buffer
+            .get(self.cursor)
+            .ok_or(DecodeError::UnexpectedEnd)?;
         self.cursor += 1;
-        item
+        Ok(item)
+    }
+
+    fn read_u16(&mut self) -> Result<u16, DecodeError> {
+        let bytes = [self.read_u8()?, self.read_u8()?];
+        Ok(self.endianness.u16_from_bytes(bytes))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, DecodeError> {
+        let bytes = [
+            self.read_u8()?,
+            self.read_u8()?,
+            self.read_u8()?,
+            self.read_u8()?,
+        ];
+        Ok(self.endianness.u32_from_bytes(bytes))
+    }
+
+    fn read_f64(&mut self) -> Result<f64, DecodeError> {
+        let mut bytes = [0; 8];
+        for byte in &mut bytes {
+            *byte = self.read_u8()?;
+        }
+        Ok(self.endianness.f64_from_bytes(bytes))
     }
 
-    fn read_u16(&mut self) -> u16 {
-        u16::from_le_bytes([self.read_u8(), self.read_u8()])
+    fn read_varint(&mut self) -> Result<u16, DecodeError> {
+        let offset = self.cursor;
+        let mut value = 0u32;
+        // A u16 needs at most three groups of seven bits
+        for shift in [0, 7, 14] {
+            let byte = self.read_u8()?;
+            value |= u32::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return u16::try_from(value).map_err(|_| DecodeError::InvalidVarint { offset });
+            }
+        }
+        Err(DecodeError::InvalidVarint { offset })
     }
 
-    fn register(&mut self) -> Register {
-        Register::from_u8(self.read_u8())
+    fn register(&mut self) -> Result<Register, DecodeError> {
+        let offset = self.cursor;
+        let register = self.read_u8()?;
+        Register::from_u8(register).ok_or(DecodeError::InvalidRegister { register, offset })
     }
 
-    fn value(&mut self, is_register: bool) -> Value {
-        if is_register {
-            Value::Register(self.register())
+    fn value(&mut self, is_register: bool) -> Result<Value, DecodeError> {
+        if is_register && self.buffer.get(self.cursor) == Some(&FLOAT_IMMEDIATE) {
+            self.cursor += 1;
+            Ok(Value::Float(self.read_f64()?))
+        } else if is_register {
+            Ok(Value::Register(self.register()?))
+        } else if self.varint {
+            Ok(Value::Uint(self.read_varint()?))
         } else {
-            Value::Uint(self.read_u16())
+            Ok(Value::Uint(self.read_u16()?))
         }
     }
 
-    fn address(&mut self) -> Address {
-        Address(self.read_u16())
+    fn angle_unit(&mut self) -> Result<AngleUnit, DecodeError> {
+        let offset = self.cursor;
+        let unit = self.read_u8()?;
+        AngleUnit::from_u8(unit).ok_or(DecodeError::InvalidAngleUnit { unit, offset })
     }
-}
 
-fn parse_next_instruction(buffer: &[u8]) -> (usize, Instruction) {
-    let mut p = Program { buffer, cursor: 0 };
+    fn condition(&mut self) -> Result<Condition, DecodeError> {
+        let offset = self.cursor;
+        let condition = self.read_u8()?;
+        Condition::from_u8(condition).ok_or(DecodeError::InvalidCondition { condition, offset })
+    }
 
-    let opcode = p.read_u8();
+    fn address(&mut self) -> Result<Address, DecodeError> {
+        if self.wide_addresses {
+            Ok(Address(self.read_u32()?))
+        } else {
+            Ok(Address(self.read_u16()?.into()))
+        }
+    }
+}
+
+fn parse_next_instruction(p: &mut Program) -> Result<Instruction, DecodeError> {
+    let offset = p.cursor;
+    let opcode = p.read_u8()?;
 
     // If the high bit is set the second operand should be treated as a register
     let high_bit_set = opcode & 0b1000_0000 != 0;
 
-    let opcode = Opcode::try_from(opcode & 0b0111_1111)
-        .unwrap_or_else(|_| panic!("invalid instruction: {:#04x}", opcode));
+    // For instructions taking two values, the next bit does the same for the first operand
+    let first_is_register = opcode & 0b0100_0000 != 0;
+
+    let opcode = Opcode::try_from(opcode & 0b0011_1111)
+        .map_err(|_| DecodeError::InvalidOpcode { opcode, offset })?;
 
     use Instruction::*;
     use Opcode::*;
@@ -242,51 +1311,386 @@ fn parse_next_instruction(buffer: &[u8]) -> (usize, Instruction) {
         DRW => Draw,
         FWD => Forward,
         HLT => Halt,
-        INC => Increment(p.register()),
-        DEC => Decrement(p.register()),
-        STO => Store(p.register(), p.value(high_bit_set)),
-        ADD => Add(p.register(), p.value(high_bit_set)),
-        SUB => Sub(p.register(), p.value(high_bit_set)),
-        MUL => Multiply(p.register(), p.value(high_bit_set)),
-        DIV => Divide(p.register(), p.value(high_bit_set)),
-        JNZ => JumpIfNonZero(p.register(), p.address()),
-        JEQ => JumpIfEqual(p.register(), p.value(high_bit_set), p.address()),
-        JNE => JumpIfNotEqual(p.register(), p.value(high_bit_set), p.address()),
-        JGT => JumpIfGreaterThan(p.register(), p.value(high_bit_set), p.address()),
-        JLT => JumpIfLessThan(p.register(), p.value(high_bit_set), p.address()),
+        INC => Increment(p.register()?),
+        DEC => Decrement(p.register()?),
+        STO => Store(p.register()?, p.value(high_bit_set)?),
+        ADD => Add(p.register()?, p.value(high_bit_set)?),
+        SUB => Sub(p.register()?, p.value(high_bit_set)?),
+        MUL => Multiply(p.register()?, p.value(high_bit_set)?),
+        DIV => Divide(p.register()?, p.value(high_bit_set)?),
+        JNZ => JumpIfNonZero(p.register()?, p.address()?),
+        JEQ => JumpIfEqual(p.register()?, p.value(high_bit_set)?, p.address()?),
+        JNE => JumpIfNotEqual(p.register()?, p.value(high_bit_set)?, p.address()?),
+        JGT => JumpIfGreaterThan(p.register()?, p.value(high_bit_set)?, p.address()?),
+        JLT => JumpIfLessThan(p.register()?, p.value(high_bit_set)?, p.address()?),
+        GRAD => Gradient(p.value(high_bit_set)?),
+        MIN => Min(p.register()?, p.value(high_bit_set)?),
+        MAX => Max(p.register()?, p.value(high_bit_set)?),
+        ABS => Abs(p.register()?),
+        AUNIT => AngleUnit(p.angle_unit()?),
+        PUSH => Push(p.register()?),
+        POP => Pop(p.register()?),
+        ARC => Arc(p.value(first_is_register)?, p.value(high_bit_set)?),
+        POLY => Poly(p.value(first_is_register)?, p.value(high_bit_set)?),
+        SPIRAL => Spiral(p.value(first_is_register)?, p.value(high_bit_set)?),
+        LDI => LoadIndirect(p.register()?, p.register()?),
+        STI => StoreIndirect(p.register()?, p.register()?),
+        LOAD => Load(p.register()?, p.register()?),
+        SAVE => Save(p.register()?, p.register()?),
+        FILL => Fill,
+        HOME => Home,
+        CMP => Compare(p.register()?, p.value(high_bit_set)?),
+        JF => JumpIfFlag(p.condition()?, p.address()?),
+        PLOT => Plot,
+        DBG => Debug,
+        COLI => ColorIndex(p.value(high_bit_set)?),
+        PW => PenWidth(p.value(high_bit_set)?),
+        TIME => Time(p.register()?),
+        SKZ => SkipIfZero(p.register()?),
+        SKNZ => SkipIfNonZero(p.register()?),
+        DASH => Dash(p.value(first_is_register)?, p.value(high_bit_set)?),
+        MIRROR => Mirror(p.value(first_is_register)?, p.value(high_bit_set)?),
+        HEADING => Heading(p.register()?, p.register()?),
+        MOVE => MoveBy(p.register()?, p.register()?),
+        CVT => Convert(p.register()?, p.register()?),
+        SAMPLE => Sample(p.register()?),
+        RGB => Rgb(p.register()?, p.register()?, p.register()?),
+        SEL => Select(p.value(high_bit_set)?),
+        WAIT => Wait(p.value(high_bit_set)?),
+        JMPR => JumpRegister(p.register()?),
     };
 
-    (p.cursor, instruction)
+    Ok(instruction)
 }
 
-fn parse_header(buffer: &[u8]) -> (usize, u8, u16, u16) {
-    let version = buffer[0];
+/// The header version written by [`encode`] and the assembler.
+pub const VERSION: u8 = 0x06;
+
+/// Header flag for immediate values written as varints, see [`write_varint`].
+pub const FLAG_VARINT: u8 = 0x01;
+
+/// Header flag for jump addresses written as four bytes rather than two, for programs with more
+/// than 65536 instructions.
+pub const FLAG_WIDE_ADDRESSES: u8 = 0x02;
+
+/// Header flag for a program that ends with the source it was assembled from: the UTF-8 text
+/// followed by its length in bytes as a `u32` in the program's byte order. The length comes last
+/// rather than before the text so decoding can find where the instructions stop by reading back
+/// from the end, without knowing how long they are.
+pub const FLAG_SOURCE: u8 = 0x04;
+
+/// The most colors a palette can hold.
+pub const PALETTE_SIZE: usize = 16;
+
+/// The widest or tallest a canvas can be, so a bad header can't ask for a huge buffer.
+pub const MAX_DIMENSION: u16 = 4096;
+
+/// Everything in a program before its instructions.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Header {
+    pub width: u16,
+    pub height: u16,
+    /// Words of memory for `LOAD` and `SAVE`
+    pub memory: u16,
+    /// The `0xRRGGBB` color the canvas starts as
+    pub background: u32,
+    pub endianness: Endianness,
+    /// Colors selected by `COLI`
+    pub palette: Vec<u32>,
+    /// Immediate values are varints rather than two bytes each
+    pub varint: bool,
+    /// Jump addresses are four bytes rather than two
+    pub wide_addresses: bool,
+    /// The assembly the program was built from, when it was embedded after the instructions
+    pub source: Option<String>,
+}
+
+impl Header {
+    /// A header for a `width` by `height` little endian program with no memory or palette and a
+    /// black background.
+    pub fn new(width: u16, height: u16) -> Self {
+        Header {
+            width,
+            height,
+            memory: 0,
+            background: 0,
+            endianness: Endianness::Little,
+            palette: Vec::new(),
+            varint: false,
+            wide_addresses: false,
+            source: None,
+        }
+    }
+}
+
+/// Read the header, checking the version is one we understand.
+fn parse_header(p: &mut Program) -> Result<Header, DecodeError> {
+    let version = p.read_u8()?;
+    if !(0x01..=VERSION).contains(&version) {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+
+    // Programs were always little endian until version 4 recorded the byte order
+    if version >= 0x04 {
+        let endianness = p.read_u8()?;
+        p.endianness =
+            Endianness::from_u8(endianness).ok_or(DecodeError::InvalidEndianness(endianness))?;
+    }
+
+    let width = p.read_u16()?;
+    let height = p.read_u16()?;
+    let valid = 1..=MAX_DIMENSION;
+    if !valid.contains(&width) || !valid.contains(&height) {
+        return Err(DecodeError::InvalidSize { width, height });
+    }
+
+    // Later versions add fields to the end of the previous version's header
+    let memory = if version >= 0x02 { p.read_u16()? } else { 0 };
+    let background = if version >= 0x03 { p.read_u32()? } else { 0 };
+
+    let mut palette = Vec::new();
+    if version >= 0x05 {
+        let len = p.read_u8()?;
+        if usize::from(len) > PALETTE_SIZE {
+            return Err(DecodeError::PaletteTooLarge(len));
+        }
+        for _ in 0..len {
+            palette.push(p.read_u32()?);
+        }
+    }
 
-    let width = u16::from_le_bytes([buffer[1], buffer[2]]);
-    let height = u16::from_le_bytes([buffer[3], buffer[4]]);
+    let mut source = None;
+    if version >= 0x06 {
+        let flags = p.read_u8()?;
+        if flags & !(FLAG_VARINT | FLAG_WIDE_ADDRESSES | FLAG_SOURCE) != 0 {
+            return Err(DecodeError::UnknownFlags(flags));
+        }
+        p.varint = flags & FLAG_VARINT != 0;
+        p.wide_addresses = flags & FLAG_WIDE_ADDRESSES != 0;
+        if flags & FLAG_SOURCE != 0 {
+            source = Some(p.take_source()?);
+        }
+    }
 
-    // We've read 5 bytes
-    let read = 5;
+    Ok(Header {
+        width,
+        height,
+        memory,
+        background,
+        endianness: p.endianness,
+        palette,
+        varint: p.varint,
+        wide_addresses: p.wide_addresses,
+        source,
+    })
+}
 
-    (read, version, width, height)
+/// Decode a program into its header and instructions.
+pub fn decode(buffer: &[u8]) -> Result<(Header, Vec<Instruction>), DecodeError> {
+    let (header, program, _) = decode_with_offsets(buffer)?;
+    let program = program
+        .into_iter()
+        .map(|(_, instruction)| instruction)
+        .collect();
+    Ok((header, program))
 }
 
-pub fn decode(buffer: &[u8]) -> (u16, u16, Vec<Instruction>) {
-    let (mut i, version, width, height) = parse_header(&buffer);
+/// Like [`decode`], but pairs each instruction with the byte offset it starts at, and also returns
+/// the offset the instructions end at. Anything after that is the embedded source, see
+/// [`FLAG_SOURCE`].
+#[allow(clippy::type_complexity)]
+pub fn decode_with_offsets(
+    buffer: &[u8],
+) -> Result<(Header, Vec<(usize, Instruction)>, usize), DecodeError> {
+    let mut p = Program {
+        buffer,
+        cursor: 0,
+        endianness: Endianness::Little,
+        varint: false,
+        wide_addresses: false,
+    };
 
-    assert_eq!(0x01, version);
+    let header = parse_header(&mut p)?;
 
     let mut program = Vec::new();
-    loop {
-        if i >= buffer.len() {
-            break;
+    while !p.is_empty() {
+        let offset = p.cursor;
+        program.push((offset, parse_next_instruction(&mut p)?));
+    }
+
+    Ok((header, program, p.buffer.len()))
+}
+
+/// A summary of a program's size and makeup, from [`program_stats`].
+#[derive(Debug, PartialEq, Clone)]
+pub struct ProgramStats {
+    pub instruction_count: usize,
+    /// The whole program including its header
+    pub byte_size: usize,
+    /// How many times each opcode is used, most used first, leaving out unused opcodes
+    pub opcode_histogram: Vec<(Opcode, usize)>,
+}
+
+impl ProgramStats {
+    /// The stats as a single line JSON object, with the histogram keyed by mnemonic.
+    pub fn to_json(&self) -> String {
+        let histogram: Vec<String> = self
+            .opcode_histogram
+            .iter()
+            .map(|(opcode, count)| format!("\"{}\":{}", opcode.mnemonic(), count))
+            .collect();
+        format!(
+            "{{\"instruction_count\":{},\"byte_size\":{},\"opcode_histogram\":{{{}}}}}",
+            self.instruction_count,
+            self.byte_size,
+            histogram.join(",")
+        )
+    }
+}
+
+/// Decode a program and count its instructions, bytes and opcodes without running it.
+pub fn program_stats(buffer: &[u8]) -> Result<ProgramStats, DecodeError> {
+    let (_, program) = decode(buffer)?;
+
+    // Opcodes fit in the six bits not used to flag registers
+    let mut counts = [0; 0x40];
+    for instruction in &program {
+        counts[instruction.opcode() as usize] += 1;
+    }
+    let mut opcode_histogram: Vec<(Opcode, usize)> = (0..0x40u8)
+        .filter(|&byte| counts[usize::from(byte)] > 0)
+        .filter_map(|byte| Some((Opcode::try_from(byte).ok()?, counts[usize::from(byte)])))
+        .collect();
+    // Stable, so ties stay in opcode order
+    opcode_histogram.sort_by_key(|&(_, count)| Reverse(count));
+
+    Ok(ProgramStats {
+        instruction_count: program.len(),
+        byte_size: buffer.len(),
+        opcode_histogram,
+    })
+}
+
+/// Append `value` as an LEB128 varint: seven bits per byte, least significant first, with the
+/// high bit set on every byte but the last. Values below 128 take one byte and a `u16` at most
+/// three.
+pub fn write_varint(out: &mut Vec<u8>, mut value: u16) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Encode a program the way the assembler does, so [`decode`] gives back the same header and
+/// instructions. The palette should have at most [`PALETTE_SIZE`] colors, and jump targets past
+/// `u16::MAX` need [`Header::wide_addresses`].
+pub fn encode(header: &Header, program: &[Instruction]) -> Vec<u8> {
+    let endianness = header.endianness;
+
+    let mut out = vec![VERSION, endianness as u8];
+    out.extend_from_slice(&endianness.u16_to_bytes(header.width));
+    out.extend_from_slice(&endianness.u16_to_bytes(header.height));
+    out.extend_from_slice(&endianness.u16_to_bytes(header.memory));
+    out.extend_from_slice(&endianness.u32_to_bytes(header.background));
+    out.push(header.palette.len() as u8);
+    for &color in &header.palette {
+        out.extend_from_slice(&endianness.u32_to_bytes(color));
+    }
+    let mut flags = 0;
+    if header.varint {
+        flags |= FLAG_VARINT;
+    }
+    if header.wide_addresses {
+        flags |= FLAG_WIDE_ADDRESSES;
+    }
+    if header.source.is_some() {
+        flags |= FLAG_SOURCE;
+    }
+    out.push(flags);
+
+    let immediate = |out: &mut Vec<u8>, n: u16| {
+        if header.varint {
+            write_varint(out, n);
+        } else {
+            out.extend_from_slice(&endianness.u16_to_bytes(n));
         }
+    };
 
-        let (bytes, instruction) = parse_next_instruction(&buffer[i..]);
-        i += bytes;
+    for instruction in program {
+        let opcode = instruction.opcode();
+        let operands = instruction.operands();
+        debug_assert!(operands
+            .iter()
+            .map(|operand| operand.kind())
+            .eq(opcode.operand_kinds().iter().copied()));
 
-        program.push(instruction);
+        let index = out.len();
+        out.push(opcode as u8);
+
+        // The high bit marks the last value as a register, the next bit the value before it
+        let mut values = operands
+            .iter()
+            .filter(|operand| operand.kind() == OperandKind::Value)
+            .count();
+        for operand in operands {
+            match operand {
+                Operand::Register(r) => out.push(r.to_u8()),
+                Operand::Value(v) => {
+                    values -= 1;
+                    // Float immediates are flagged like registers
+                    let flag = if values == 0 { 0x80 } else { 0x40 };
+                    match v {
+                        Value::Register(r) => {
+                            out[index] |= flag;
+                            out.push(r.to_u8());
+                        }
+                        Value::Uint(n) => immediate(&mut out, n),
+                        Value::Float(n) => {
+                            out[index] |= flag;
+                            out.push(FLOAT_IMMEDIATE);
+                            out.extend_from_slice(&endianness.f64_to_bytes(n));
+                        }
+                    }
+                }
+                Operand::Address(a) if header.wide_addresses => {
+                    out.extend_from_slice(&endianness.u32_to_bytes(a.0))
+                }
+                Operand::Address(a) => {
+                    debug_assert!(a.0 <= u32::from(u16::MAX), "address needs wide_addresses");
+                    out.extend_from_slice(&endianness.u16_to_bytes(a.0 as u16))
+                }
+                Operand::AngleUnit(unit) => out.push(unit as u8),
+                Operand::Condition(condition) => out.push(condition as u8),
+            }
+        }
+    }
+
+    if let Some(source) = &header.source {
+        out.extend_from_slice(source.as_bytes());
+        out.extend_from_slice(&endianness.u32_to_bytes(source.len() as u32));
+    }
+
+    out
+}
+
+/// Join two programs so `b` runs when `a` runs off its end, moving `b`'s jump targets past `a`'s
+/// instructions. Returns `None` if a moved target doesn't fit in an [`Address`], or if `b` uses
+/// `JMPR`: the addresses it jumps to are loaded into registers as plain values, by the
+/// assembler's `LEA`, so they can't be told apart from other numbers to move them.
+pub fn concat(a: &[Instruction], b: &[Instruction]) -> Option<Vec<Instruction>> {
+    if b.iter().any(|i| matches!(i, Instruction::JumpRegister(_))) {
+        return None;
     }
 
-    (width, height, program)
+    let mut program = a.to_vec();
+    for &instruction in b {
+        let mut instruction = instruction;
+        if let Some(target) = instruction.jump_target() {
+            let target = u32::try_from(target + a.len()).ok()?;
+            instruction.set_jump_target(Address(target));
+        }
+        program.push(instruction);
+    }
+    Some(program)
 }
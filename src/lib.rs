@@ -1,11 +1,41 @@
-use std::convert::TryFrom;
+//! The drawer VM, its bytecode and a pixel buffer to render into.
+//!
+//! Built with `--no-default-features --features libm` the crate is `no_std` and only needs
+//! `alloc`, for running on hardware without an operating system. Without the `std` feature,
+//! which the default `gui` feature enables, float math comes from `libm`.
+//!
+//! The `wasm` feature adds [`wasm`] bindings for running programs in the browser. Build just the
+//! library for it as a `cdylib`, without the default features so `minifb` is left out, see
+//! [`wasm`] for the command.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+
+#[cfg(all(not(feature = "std"), not(feature = "libm")))]
+compile_error!("building without the `std` feature requires the `libm` feature");
+
+use core::convert::TryFrom;
+use core::fmt;
+
+#[cfg(not(feature = "std"))]
+use alloc::string::{String, ToString};
 
 pub mod buffer;
+pub mod builder;
+pub mod compile;
 pub mod instruction;
+pub mod lint;
+pub mod log;
+mod math;
 pub mod vm;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 #[repr(u8)]
 #[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, PartialEq, Copy, Clone)]
 pub enum Opcode {
     DRW = 0x01,
     FWD = 0x02,
@@ -22,23 +52,313 @@ pub enum Opcode {
     JNE = 0x0d,
     JLT = 0x0e,
     DIV = 0x0f,
+    GRAD = 0x10,
+    MIN = 0x11,
+    MAX = 0x12,
+    ABS = 0x13,
+    AUNIT = 0x14,
+    PUSH = 0x15,
+    POP = 0x16,
+    ARC = 0x17,
+    LDI = 0x18,
+    STI = 0x19,
+    LOAD = 0x1a,
+    SAVE = 0x1b,
+    FILL = 0x1c,
+    HOME = 0x1d,
+    CMP = 0x1e,
+    JF = 0x1f,
+    PLOT = 0x20,
+    DBG = 0x21,
+    COLI = 0x22,
+    PW = 0x23,
+    TIME = 0x24,
+    SKZ = 0x25,
+    SKNZ = 0x26,
+    DASH = 0x27,
+    HEADING = 0x28,
+    RGB = 0x29,
+    SEL = 0x2a,
+    JMPR = 0x2b,
+    MIRROR = 0x2c,
+    WAIT = 0x2d,
+    POLY = 0x2e,
+    SPIRAL = 0x2f,
+    CVT = 0x30,
+    SAMPLE = 0x31,
+    MOVE = 0x32,
+}
+
+const ALL_OPCODES: &[Opcode] = &[
+    Opcode::DRW,
+    Opcode::FWD,
+    Opcode::STO,
+    Opcode::INC,
+    Opcode::ADD,
+    Opcode::DEC,
+    Opcode::JNZ,
+    Opcode::HLT,
+    Opcode::MUL,
+    Opcode::JGT,
+    Opcode::SUB,
+    Opcode::JEQ,
+    Opcode::JNE,
+    Opcode::JLT,
+    Opcode::DIV,
+    Opcode::GRAD,
+    Opcode::MIN,
+    Opcode::MAX,
+    Opcode::ABS,
+    Opcode::AUNIT,
+    Opcode::PUSH,
+    Opcode::POP,
+    Opcode::ARC,
+    Opcode::LDI,
+    Opcode::STI,
+    Opcode::LOAD,
+    Opcode::SAVE,
+    Opcode::FILL,
+    Opcode::HOME,
+    Opcode::CMP,
+    Opcode::JF,
+    Opcode::PLOT,
+    Opcode::DBG,
+    Opcode::COLI,
+    Opcode::PW,
+    Opcode::TIME,
+    Opcode::SKZ,
+    Opcode::SKNZ,
+    Opcode::DASH,
+    Opcode::HEADING,
+    Opcode::RGB,
+    Opcode::SEL,
+    Opcode::JMPR,
+    Opcode::MIRROR,
+    Opcode::WAIT,
+    Opcode::POLY,
+    Opcode::SPIRAL,
+    Opcode::CVT,
+    Opcode::SAMPLE,
+    Opcode::MOVE,
+];
+
+/// The kind of an operand an [`Opcode`] expects, in the order they're encoded.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum OperandKind {
+    /// A register byte
+    Register,
+    /// A register byte or a `u16` immediate, chosen by the opcode's flag bits
+    Value,
+    /// A `u16` instruction index to jump to
+    Address,
+    /// An angle unit byte
+    AngleUnit,
+    /// A jump condition byte
+    Condition,
+}
+
+impl Opcode {
+    /// Every opcode, in byte order.
+    pub fn all() -> &'static [Opcode] {
+        ALL_OPCODES
+    }
+
+    /// The operands this opcode expects, in the order they're encoded.
+    pub fn operand_kinds(self) -> &'static [OperandKind] {
+        use OperandKind::*;
+
+        match self {
+            Opcode::DRW | Opcode::FWD | Opcode::HLT => &[],
+            Opcode::FILL | Opcode::HOME | Opcode::PLOT | Opcode::DBG => &[],
+            Opcode::INC | Opcode::DEC | Opcode::ABS | Opcode::PUSH | Opcode::POP => &[Register],
+            Opcode::TIME | Opcode::SKZ | Opcode::SKNZ | Opcode::SAMPLE => &[Register],
+            Opcode::STO | Opcode::ADD | Opcode::SUB | Opcode::MUL | Opcode::DIV => {
+                &[Register, Value]
+            }
+            Opcode::MIN | Opcode::MAX | Opcode::CMP => &[Register, Value],
+            Opcode::LDI | Opcode::STI | Opcode::LOAD | Opcode::SAVE => &[Register, Register],
+            Opcode::HEADING | Opcode::CVT | Opcode::MOVE => &[Register, Register],
+            Opcode::RGB => &[Register, Register, Register],
+            Opcode::SEL | Opcode::WAIT => &[Value],
+            Opcode::JMPR => &[Register],
+            Opcode::JNZ => &[Register, Address],
+            Opcode::JEQ | Opcode::JNE | Opcode::JGT | Opcode::JLT => &[Register, Value, Address],
+            Opcode::JF => &[Condition, Address],
+            Opcode::GRAD | Opcode::COLI | Opcode::PW => &[Value],
+            Opcode::ARC | Opcode::DASH | Opcode::MIRROR | Opcode::POLY | Opcode::SPIRAL => {
+                &[Value, Value]
+            }
+            Opcode::AUNIT => &[AngleUnit],
+        }
+    }
+
+    /// The name used for this opcode in assembly.
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Opcode::DRW => "DRW",
+            Opcode::FWD => "FWD",
+            Opcode::STO => "STO",
+            Opcode::INC => "INC",
+            Opcode::ADD => "ADD",
+            Opcode::DEC => "DEC",
+            Opcode::JNZ => "JNZ",
+            Opcode::HLT => "HLT",
+            Opcode::MUL => "MUL",
+            Opcode::JGT => "JGT",
+            Opcode::SUB => "SUB",
+            Opcode::JEQ => "JEQ",
+            Opcode::JNE => "JNE",
+            Opcode::JLT => "JLT",
+            Opcode::DIV => "DIV",
+            Opcode::GRAD => "GRAD",
+            Opcode::MIN => "MIN",
+            Opcode::MAX => "MAX",
+            Opcode::ABS => "ABS",
+            Opcode::AUNIT => "AUNIT",
+            Opcode::PUSH => "PUSH",
+            Opcode::POP => "POP",
+            Opcode::ARC => "ARC",
+            Opcode::LDI => "LDI",
+            Opcode::STI => "STI",
+            Opcode::LOAD => "LOAD",
+            Opcode::SAVE => "SAVE",
+            Opcode::FILL => "FILL",
+            Opcode::HOME => "HOME",
+            Opcode::CMP => "CMP",
+            Opcode::JF => "JF",
+            Opcode::PLOT => "PLOT",
+            Opcode::DBG => "DBG",
+            Opcode::COLI => "COLI",
+            Opcode::PW => "PW",
+            Opcode::TIME => "TIME",
+            Opcode::SKZ => "SKZ",
+            Opcode::SKNZ => "SKNZ",
+            Opcode::DASH => "DASH",
+            Opcode::HEADING => "HEADING",
+            Opcode::RGB => "RGB",
+            Opcode::SEL => "SEL",
+            Opcode::JMPR => "JMPR",
+            Opcode::MIRROR => "MIRROR",
+            Opcode::WAIT => "WAIT",
+            Opcode::POLY => "POLY",
+            Opcode::SPIRAL => "SPIRAL",
+            Opcode::CVT => "CVT",
+            Opcode::SAMPLE => "SAMPLE",
+            Opcode::MOVE => "MOVE",
+        }
+    }
+}
+
+/// Returned when a byte or mnemonic doesn't name an [`Opcode`], or a name doesn't name a
+/// register.
+#[derive(Debug, PartialEq)]
+pub enum OpcodeParseError {
+    InvalidByte(u8),
+    UnknownMnemonic(String),
+    UnknownRegister(String),
+}
+
+impl fmt::Display for OpcodeParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OpcodeParseError::InvalidByte(byte) => write!(f, "invalid opcode: {:#04x}", byte),
+            OpcodeParseError::UnknownMnemonic(mnemonic) => {
+                write!(f, "unknown instruction: {}", mnemonic)
+            }
+            OpcodeParseError::UnknownRegister(name) => write!(f, "not a register: {}", name),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for OpcodeParseError {}
+
+/// The byte naming a register in bytecode, `A` to `H` being 0 to 7 and `S` to `Z` 8 to 15. See
+/// [`Register::from_u8`](instruction::Register::from_u8) for the other direction.
+pub fn register_from_str(name: &str) -> Result<u8, OpcodeParseError> {
+    match name {
+        "A" => Ok(0x0),
+        "B" => Ok(0x1),
+        "C" => Ok(0x2),
+        "D" => Ok(0x3),
+        "E" => Ok(0x4),
+        "F" => Ok(0x5),
+        "G" => Ok(0x6),
+        "H" => Ok(0x7),
+        "S" => Ok(0x8),
+        "T" => Ok(0x9),
+        "U" => Ok(0xa),
+        "V" => Ok(0xb),
+        "W" => Ok(0xc),
+        "X" => Ok(0xd),
+        "Y" => Ok(0xe),
+        "Z" => Ok(0xf),
+        _ => Err(OpcodeParseError::UnknownRegister(name.to_string())),
+    }
 }
 
 impl TryFrom<u8> for Opcode {
-    type Error = ();
+    type Error = OpcodeParseError;
 
     fn try_from(input: u8) -> Result<Self, Self::Error> {
-        if input <= 0x0f {
-            // Safety: Opcode is repr(u8) and the input is <= the largest Opcode varient
-            Ok(unsafe { std::mem::transmute::<u8, Self>(input) })
-        } else {
-            Err(())
+        match input {
+            0x01 => Ok(Opcode::DRW),
+            0x02 => Ok(Opcode::FWD),
+            0x03 => Ok(Opcode::STO),
+            0x04 => Ok(Opcode::INC),
+            0x05 => Ok(Opcode::ADD),
+            0x06 => Ok(Opcode::DEC),
+            0x07 => Ok(Opcode::JNZ),
+            0x08 => Ok(Opcode::HLT),
+            0x09 => Ok(Opcode::MUL),
+            0x0a => Ok(Opcode::JGT),
+            0x0b => Ok(Opcode::SUB),
+            0x0c => Ok(Opcode::JEQ),
+            0x0d => Ok(Opcode::JNE),
+            0x0e => Ok(Opcode::JLT),
+            0x0f => Ok(Opcode::DIV),
+            0x10 => Ok(Opcode::GRAD),
+            0x11 => Ok(Opcode::MIN),
+            0x12 => Ok(Opcode::MAX),
+            0x13 => Ok(Opcode::ABS),
+            0x14 => Ok(Opcode::AUNIT),
+            0x15 => Ok(Opcode::PUSH),
+            0x16 => Ok(Opcode::POP),
+            0x17 => Ok(Opcode::ARC),
+            0x18 => Ok(Opcode::LDI),
+            0x19 => Ok(Opcode::STI),
+            0x1a => Ok(Opcode::LOAD),
+            0x1b => Ok(Opcode::SAVE),
+            0x1c => Ok(Opcode::FILL),
+            0x1d => Ok(Opcode::HOME),
+            0x1e => Ok(Opcode::CMP),
+            0x1f => Ok(Opcode::JF),
+            0x20 => Ok(Opcode::PLOT),
+            0x21 => Ok(Opcode::DBG),
+            0x22 => Ok(Opcode::COLI),
+            0x23 => Ok(Opcode::PW),
+            0x24 => Ok(Opcode::TIME),
+            0x25 => Ok(Opcode::SKZ),
+            0x26 => Ok(Opcode::SKNZ),
+            0x27 => Ok(Opcode::DASH),
+            0x28 => Ok(Opcode::HEADING),
+            0x29 => Ok(Opcode::RGB),
+            0x2a => Ok(Opcode::SEL),
+            0x2b => Ok(Opcode::JMPR),
+            0x2c => Ok(Opcode::MIRROR),
+            0x2d => Ok(Opcode::WAIT),
+            0x2e => Ok(Opcode::POLY),
+            0x2f => Ok(Opcode::SPIRAL),
+            0x30 => Ok(Opcode::CVT),
+            0x31 => Ok(Opcode::SAMPLE),
+            0x32 => Ok(Opcode::MOVE),
+            _ => Err(OpcodeParseError::InvalidByte(input)),
         }
     }
 }
 
 impl TryFrom<&str> for Opcode {
-    type Error = ();
+    type Error = OpcodeParseError;
 
     fn try_from(input: &str) -> Result<Self, Self::Error> {
         match input {
@@ -57,7 +377,42 @@ impl TryFrom<&str> for Opcode {
             "JNE" => Ok(Opcode::JNE),
             "JLT" => Ok(Opcode::JLT),
             "DIV" => Ok(Opcode::DIV),
-            _ => Err(()),
+            "GRAD" => Ok(Opcode::GRAD),
+            "MIN" => Ok(Opcode::MIN),
+            "MAX" => Ok(Opcode::MAX),
+            "ABS" => Ok(Opcode::ABS),
+            "AUNIT" => Ok(Opcode::AUNIT),
+            "PUSH" => Ok(Opcode::PUSH),
+            "POP" => Ok(Opcode::POP),
+            "ARC" => Ok(Opcode::ARC),
+            "LDI" => Ok(Opcode::LDI),
+            "STI" => Ok(Opcode::STI),
+            "LOAD" => Ok(Opcode::LOAD),
+            "SAVE" => Ok(Opcode::SAVE),
+            "FILL" => Ok(Opcode::FILL),
+            "HOME" => Ok(Opcode::HOME),
+            "CMP" => Ok(Opcode::CMP),
+            "JF" => Ok(Opcode::JF),
+            "PLOT" => Ok(Opcode::PLOT),
+            "DBG" => Ok(Opcode::DBG),
+            "COLI" => Ok(Opcode::COLI),
+            "PW" => Ok(Opcode::PW),
+            "TIME" => Ok(Opcode::TIME),
+            "SKZ" => Ok(Opcode::SKZ),
+            "SKNZ" => Ok(Opcode::SKNZ),
+            "DASH" => Ok(Opcode::DASH),
+            "HEADING" => Ok(Opcode::HEADING),
+            "RGB" => Ok(Opcode::RGB),
+            "SEL" => Ok(Opcode::SEL),
+            "JMPR" => Ok(Opcode::JMPR),
+            "MIRROR" => Ok(Opcode::MIRROR),
+            "WAIT" => Ok(Opcode::WAIT),
+            "POLY" => Ok(Opcode::POLY),
+            "SPIRAL" => Ok(Opcode::SPIRAL),
+            "CVT" => Ok(Opcode::CVT),
+            "SAMPLE" => Ok(Opcode::SAMPLE),
+            "MOVE" => Ok(Opcode::MOVE),
+            _ => Err(OpcodeParseError::UnknownMnemonic(input.to_string())),
         }
     }
 }
@@ -0,0 +1,211 @@
+//! Checks a program for patterns that run but probably don't do what was meant, for teaching.
+
+use core::fmt;
+
+#[cfg(not(feature = "std"))]
+use alloc::{vec, vec::Vec};
+
+use crate::instruction::{FloatRegister, Instruction, Register, UintRegister, Value};
+use crate::vm::TURTLES;
+
+/// Something suspicious found by [`lint`].
+#[derive(Debug, PartialEq)]
+pub enum Lint {
+    /// `register` can be read at `pc` before anything has written it
+    UninitializedRead { register: Register, pc: usize },
+    /// Whether the instruction at `pc` draws depends on the path taken to it, as some paths pass
+    /// an odd number of `DRW`s and others an even number
+    AmbiguousPen { pc: usize },
+    /// The jump at `pc` goes to `target`, which isn't an instruction
+    JumpOutOfRange { target: usize, pc: usize },
+    /// Execution can run past the end of the program after the instruction at `pc`, halting
+    /// without a `HLT`
+    RunsOffEnd { pc: usize },
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Lint::UninitializedRead { register, pc } => {
+                write!(f, "{} may be read before it's written at {}", register, pc)
+            }
+            Lint::AmbiguousPen { pc } => {
+                write!(
+                    f,
+                    "the pen may be up or down depending on the path to {}",
+                    pc
+                )
+            }
+            Lint::JumpOutOfRange { target, pc } => {
+                write!(
+                    f,
+                    "jump to {} past the end of the program at {}",
+                    target, pc
+                )
+            }
+            Lint::RunsOffEnd { pc } => write!(f, "execution can run off the end after {}", pc),
+        }
+    }
+}
+
+/// The registers the VM gives a meaningful starting value: the angle and the position.
+pub(crate) const INITIALIZED: u16 = (1 << UintRegister::A as u16)
+    | (1 << (8 + FloatRegister::X as u16))
+    | (1 << (8 + FloatRegister::Y as u16));
+
+/// `register`'s bit in a set of registers, `A`-`H` then `S`-`Z`.
+pub(crate) fn bit(register: Register) -> u16 {
+    1 << register.to_u8()
+}
+
+/// What's known about every path to an instruction.
+#[derive(Debug, PartialEq, Copy, Clone)]
+struct State {
+    /// Registers written on every path
+    written: u16,
+    /// For each turtle, bit 0 set if some path passes an even number of its `DRW`s, bit 1 if
+    /// some path an odd number
+    pens: [u8; TURTLES],
+    /// Bit `n` set if turtle `n` may be selected
+    selected: u8,
+}
+
+impl State {
+    fn merge(self, other: State) -> State {
+        State {
+            written: self.written & other.written,
+            pens: {
+                let mut pens = self.pens;
+                for (pen, other) in pens.iter_mut().zip(other.pens) {
+                    *pen |= other;
+                }
+                pens
+            },
+            selected: self.selected | other.selected,
+        }
+    }
+
+    /// The turtles that may be selected.
+    fn turtles(self) -> impl Iterator<Item = usize> {
+        (0..TURTLES).filter(move |&turtle| self.selected & 1 << turtle != 0)
+    }
+
+    fn draw(&mut self) {
+        let certain = self.selected.count_ones() == 1;
+        for turtle in self.turtles() {
+            let pen = self.pens[turtle];
+            let toggled = (pen & 0b01) << 1 | (pen & 0b10) >> 1;
+            // Unless we know which turtle is selected, each may or may not have toggled
+            self.pens[turtle] = if certain { toggled } else { pen | toggled };
+        }
+    }
+
+    fn select(&mut self, turtle: Value) {
+        self.selected = match turtle {
+            Value::Uint(n) if usize::from(n) < TURTLES => 1 << n,
+            // SEL fails on a turtle that doesn't exist, so execution doesn't go on
+            Value::Uint(_) => 0,
+            _ => u8::MAX,
+        };
+    }
+}
+
+/// Where execution can go after the instruction at `pc`. May include indices past the end. Where
+/// `JMPR` goes isn't known, so it has none.
+fn successors(program: &[Instruction], pc: usize) -> Vec<usize> {
+    let instruction = &program[pc];
+    let mut next = Vec::new();
+    if !matches!(
+        instruction,
+        Instruction::Halt | Instruction::JumpRegister(_)
+    ) {
+        next.push(pc + 1);
+    }
+    if instruction.is_skip() {
+        next.push(pc + 2);
+    }
+    if let Some(target) = instruction.jump_target() {
+        next.push(target);
+    }
+    next
+}
+
+/// Check every instruction reachable from the first, returning lints in program order. Registers
+/// are assumed to start unwritten other than `A`, `X` and `Y`. Instructions only reached through
+/// `JMPR` aren't checked.
+pub fn lint(program: &[Instruction]) -> Vec<Lint> {
+    let mut states: Vec<Option<State>> = vec![None; program.len()];
+    let mut pending = Vec::new();
+    if !program.is_empty() {
+        states[0] = Some(State {
+            written: INITIALIZED,
+            pens: [0b01; TURTLES],
+            selected: 0b1,
+        });
+        pending.push(0);
+    }
+
+    while let Some(pc) = pending.pop() {
+        let mut state = states[pc].unwrap();
+        let instruction = &program[pc];
+        if let Some(register) = instruction.writes() {
+            state.written |= bit(register);
+        }
+        match *instruction {
+            Instruction::Draw => state.draw(),
+            Instruction::Select(turtle) => state.select(turtle),
+            _ => {}
+        }
+
+        for next in successors(program, pc) {
+            if next >= program.len() {
+                continue;
+            }
+            let merged = states[next].map_or(state, |known| known.merge(state));
+            if states[next] != Some(merged) {
+                states[next] = Some(merged);
+                pending.push(next);
+            }
+        }
+    }
+
+    let mut lints = Vec::new();
+    for (pc, instruction) in program.iter().enumerate() {
+        let state = match states[pc] {
+            Some(state) => state,
+            None => continue,
+        };
+
+        for register in instruction.reads() {
+            if state.written & bit(register) == 0 {
+                lints.push(Lint::UninitializedRead { register, pc });
+            }
+        }
+
+        // PLOT and FILL draw whether the pen is down or not
+        let moves = matches!(
+            instruction,
+            Instruction::Forward
+                | Instruction::MoveBy(..)
+                | Instruction::Arc(..)
+                | Instruction::Poly(..)
+                | Instruction::Spiral(..)
+        );
+        if moves && state.turtles().any(|turtle| state.pens[turtle] == 0b11) {
+            lints.push(Lint::AmbiguousPen { pc });
+        }
+
+        if let Some(target) = instruction.jump_target() {
+            if target >= program.len() {
+                lints.push(Lint::JumpOutOfRange { target, pc });
+            }
+        }
+        if successors(program, pc)
+            .iter()
+            .any(|&next| next >= program.len() && Some(next) != instruction.jump_target())
+        {
+            lints.push(Lint::RunsOffEnd { pc });
+        }
+    }
+    lints
+}
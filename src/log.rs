@@ -0,0 +1,63 @@
+//! Diagnostics from the VM and the renderer, sent through a [`Logger`] so they can be captured,
+//! filtered or dropped instead of always going to STDERR.
+
+use core::fmt;
+
+/// How important a diagnostic is, least first.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
+pub enum Level {
+    /// Output asked for while debugging a program, like the registers printed by `DBG`
+    Debug,
+    /// Progress, like a program finishing
+    Info,
+    /// Something went wrong but running carries on, like a register overflowing
+    Warning,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Level::Debug => write!(f, "debug"),
+            Level::Info => write!(f, "info"),
+            Level::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// Somewhere to send diagnostics. Closures taking a [`Level`] and the message are loggers, so
+/// tests can collect what's logged.
+pub trait Logger: Send {
+    fn log(&mut self, level: Level, message: &str);
+}
+
+impl<F: FnMut(Level, &str) + Send> Logger for F {
+    fn log(&mut self, level: Level, message: &str) {
+        self(level, message)
+    }
+}
+
+/// Print diagnostics at `min` or above to STDERR, prefixed with their level. Nothing is printed
+/// when `min` is `None`.
+#[cfg(feature = "std")]
+#[derive(Debug, Copy, Clone)]
+pub struct Stderr {
+    pub min: Option<Level>,
+}
+
+#[cfg(feature = "std")]
+impl Default for Stderr {
+    fn default() -> Self {
+        Stderr {
+            min: Some(Level::Debug),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Logger for Stderr {
+    fn log(&mut self, level: Level, message: &str) {
+        if matches!(self.min, Some(min) if level >= min) {
+            eprintln!("{}: {}", level, message);
+        }
+    }
+}
@@ -1,102 +1,971 @@
 use minifb::{Scale, Window, WindowOptions};
 
-use std::io::{stdin, Read};
-use std::sync::mpsc::channel;
-use std::thread;
+use std::fs::File;
+use std::io::{stdin, stdout, BufWriter, Read, Write};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
 
-use drawer::buffer::Buffer;
-use drawer::instruction::decode;
-use drawer::vm::Vm;
+use drawer::buffer::{dot, Buffer};
+use drawer::compile::CompiledProgram;
+use drawer::instruction::{decode, program_stats, Instruction, Register};
+use drawer::lint::{lint, Lint};
+use drawer::log::{Level, Logger, Stderr};
+use drawer::register_from_str;
+use drawer::vm::{DrawEvent, PenState, Vm};
 
 enum Event {
-    Pixel((isize, isize, u32)),
+    Draw(DrawEvent),
     Terminated,
 }
 
-fn main() {
-    let mut input = Vec::new();
-    stdin().read_to_end(&mut input).unwrap();
+#[derive(Copy, Clone)]
+enum BlendMode {
+    /// New pixels replace what's there, blending only when they're translucent
+    Overwrite,
+    /// New pixels are added to what's there, brightening towards white where strokes overlap
+    Additive,
+}
 
-    let (width, height, program) = decode(&input);
+#[derive(Copy, Clone)]
+enum WrapMode {
+    /// Pixels outside the canvas are dropped
+    Clip,
+    /// Pixels outside the canvas wrap around to the opposite edge
+    Wrap,
+    /// The canvas grows to fit pixels drawn outside it
+    Grow,
+}
 
-    let mut vm = Vm::default();
+/// Where 0,0 is on the canvas
+#[derive(Copy, Clone)]
+enum Origin {
+    Center,
+    TopLeft,
+    /// A pixel offset from the top left
+    Custom(isize, isize),
+}
 
-    let width = width as usize;
-    let height = height as usize;
+impl Origin {
+    fn position(self, width: usize, height: usize) -> (isize, isize) {
+        match self {
+            Origin::Center => ((width / 2) as isize, (height / 2) as isize),
+            Origin::TopLeft => (0, 0),
+            Origin::Custom(x, y) => (x, y),
+        }
+    }
+}
 
-    let (tx, rx) = channel();
-    let worker = thread::spawn(move || {
-        while !vm.is_terminated() {
-            if let Some(pixel) = vm.step(&program) {
-                tx.send(Event::Pixel(pixel)).unwrap();
+/// How draw events are turned into pixels
+#[derive(Copy, Clone)]
+struct Rendering {
+    blend: BlendMode,
+    wrap: WrapMode,
+    /// Draw pen down dots as anti-aliased discs rather than hard pixels
+    smooth: bool,
+    /// Where warnings from the VM and renderer go, set with `--log`
+    logger: Stderr,
+}
+
+struct Options {
+    scale: Scale,
+    rendering: Rendering,
+    /// Where 0,0 is on each canvas
+    origin: Origin,
+    /// Print the most executed instructions when a program finishes
+    profile: bool,
+    /// Write pixels to STDOUT instead of opening a window
+    stream: bool,
+    /// Write the finished drawing to this path as a PPM image instead of opening a window
+    ppm: Option<String>,
+    /// Let `DBG` print registers
+    debug: bool,
+    /// Print each instruction and drawn pixel to STDERR as it runs
+    trace: bool,
+    /// Draw each pixel the pen passes through once
+    pixel_steps: bool,
+    /// Skip pixels drawn the same way as the one just before
+    dedup: bool,
+    /// Stop a program that goes this many cycles without drawing or changing a register
+    watchdog: Option<usize>,
+    /// Read programs as hex bytes instead of binary
+    hex: bool,
+    /// Once a program finishes, rotate the palette colors it drew with every frame
+    cycle_palette: bool,
+    /// Print a summary of each run as JSON to STDERR when it finishes
+    stats: bool,
+    /// Print each program's size and opcode counts as JSON instead of running it
+    info: bool,
+    /// Reject programs with lints and stop on reads of unwritten registers
+    strict: bool,
+    /// Initial values for `A` to `H` then `S` to `Z`, when any are set with `--set`
+    registers: Option<([u16; 8], [f64; 8])>,
+    /// The shortest time between window updates, unlimited when `None`
+    update_rate: Option<Duration>,
+    /// Assemble this source file and run it, again each time it changes
+    watch: Option<String>,
+    /// Draw faint gridlines this many pixels apart under the drawing
+    grid: Option<usize>,
+    /// Draw the X and Y axes under the drawing
+    axes: bool,
+    /// Programs to run side by side, read from STDIN when empty
+    paths: Vec<String>,
+}
+
+impl Options {
+    fn parse() -> Result<Self, String> {
+        let mut options = Options {
+            scale: Scale::X1,
+            rendering: Rendering {
+                blend: BlendMode::Overwrite,
+                wrap: WrapMode::Clip,
+                smooth: false,
+                logger: Stderr::default(),
+            },
+            origin: Origin::Center,
+            profile: false,
+            stream: false,
+            ppm: None,
+            debug: false,
+            trace: false,
+            pixel_steps: false,
+            dedup: false,
+            watchdog: None,
+            hex: false,
+            cycle_palette: false,
+            stats: false,
+            info: false,
+            strict: false,
+            registers: None,
+            // Limit to max ~60 fps update rate
+            update_rate: Some(Duration::from_micros(16600)),
+            watch: None,
+            grid: None,
+            axes: false,
+            paths: Vec::new(),
+        };
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--scale" => options.scale = parse_scale(args.next())?,
+                "--additive" => options.rendering.blend = BlendMode::Additive,
+                "--wrap" => options.rendering.wrap = WrapMode::Wrap,
+                "--grow" => options.rendering.wrap = WrapMode::Grow,
+                "--smooth" => options.rendering.smooth = true,
+                "--log" => options.rendering.logger.min = parse_log_level(args.next())?,
+                "--origin" => options.origin = parse_origin(args.next())?,
+                "--profile" => options.profile = true,
+                "--stream" => options.stream = true,
+                "--ppm" => options.ppm = Some(args.next().ok_or("missing PPM path")?),
+                "--debug" => options.debug = true,
+                "--trace" => options.trace = true,
+                "--pixel-steps" => options.pixel_steps = true,
+                "--dedup" => options.dedup = true,
+                "--watchdog" => options.watchdog = Some(parse_cycles(args.next())?),
+                "--strict" => options.strict = true,
+                "--stats" => options.stats = true,
+                "--info" => options.info = true,
+                "--hex" => options.hex = true,
+                "--cycle-palette" => options.cycle_palette = true,
+                "--set" => options.set_register(args.next())?,
+                "--fps" => options.update_rate = Some(parse_fps(args.next())?),
+                "--no-limit" => options.update_rate = None,
+                "--watch" => options.watch = Some(args.next().ok_or("missing source path")?),
+                "--grid" => options.grid = Some(parse_spacing(args.next())?),
+                "--axes" => options.axes = true,
+                _ if arg.starts_with("--") => return Err(format!("unknown argument: {}", arg)),
+                _ => options.paths.push(arg),
             }
         }
-        tx.send(Event::Terminated).unwrap();
-        eprintln!("worker finished");
+
+        let headless = options.stream || options.ppm.is_some();
+        if options.watch.is_some() && (headless || options.hex || !options.paths.is_empty()) {
+            return Err(
+                "--watch can't be used with --stream, --ppm, --hex or programs".to_string(),
+            );
+        }
+
+        Ok(options)
+    }
+
+    /// Parse a `--set` argument like `A=90` into the initial register values.
+    fn set_register(&mut self, input: Option<String>) -> Result<(), String> {
+        let input = input.ok_or("missing register value")?;
+        let (name, value) = input
+            .split_once('=')
+            .ok_or_else(|| format!("expected REGISTER=VALUE: {}", input))?;
+        let invalid = || format!("invalid value for {}: {}", name, value);
+
+        let register = register_from_str(name).map_err(|e| e.to_string())?;
+        let (uints, floats) = self.registers.get_or_insert(([0; 8], [0.0; 8]));
+        match Register::from_u8(register) {
+            Some(Register::UintRegister(r)) => {
+                uints[r as usize] = value.parse().map_err(|_| invalid())?
+            }
+            Some(Register::FloatRegister(r)) => {
+                floats[r as usize] = value.parse().map_err(|_| invalid())?
+            }
+            None => unreachable!("register_from_str only names registers"),
+        }
+        Ok(())
+    }
+}
+
+fn parse_scale(input: Option<String>) -> Result<Scale, String> {
+    match input.as_deref() {
+        Some("1") => Ok(Scale::X1),
+        Some("2") => Ok(Scale::X2),
+        Some("4") => Ok(Scale::X4),
+        Some("8") => Ok(Scale::X8),
+        Some(scale) => Err(format!(
+            "unsupported scale: {} (expected 1, 2, 4 or 8)",
+            scale
+        )),
+        None => Err("missing scale".to_string()),
+    }
+}
+
+fn parse_origin(input: Option<String>) -> Result<Origin, String> {
+    let input = input.ok_or("missing origin")?;
+    match input.as_str() {
+        "center" => Ok(Origin::Center),
+        "top-left" => Ok(Origin::TopLeft),
+        _ => {
+            let invalid = || {
+                format!(
+                    "invalid origin: {} (expected center, top-left or X,Y)",
+                    input
+                )
+            };
+            let (x, y) = input.split_once(',').ok_or_else(invalid)?;
+            let x = x.parse().map_err(|_| invalid())?;
+            let y = y.parse().map_err(|_| invalid())?;
+            Ok(Origin::Custom(x, y))
+        }
+    }
+}
+
+fn parse_cycles(input: Option<String>) -> Result<usize, String> {
+    let input = input.ok_or("missing cycle count")?;
+    match input.parse::<usize>() {
+        Ok(cycles) if cycles > 0 => Ok(cycles),
+        _ => Err(format!("invalid cycle count: {}", input)),
+    }
+}
+
+fn parse_spacing(input: Option<String>) -> Result<usize, String> {
+    let input = input.ok_or("missing grid spacing")?;
+    match input.parse::<usize>() {
+        Ok(spacing) if spacing > 0 => Ok(spacing),
+        _ => Err(format!("invalid grid spacing: {}", input)),
+    }
+}
+
+fn parse_log_level(input: Option<String>) -> Result<Option<Level>, String> {
+    match input.as_deref() {
+        Some("debug") => Ok(Some(Level::Debug)),
+        Some("info") => Ok(Some(Level::Info)),
+        Some("warning") => Ok(Some(Level::Warning)),
+        Some("off") => Ok(None),
+        Some(level) => Err(format!(
+            "unsupported log level: {} (expected debug, info, warning or off)",
+            level
+        )),
+        None => Err("missing log level".to_string()),
+    }
+}
+
+fn parse_fps(input: Option<String>) -> Result<Duration, String> {
+    let input = input.ok_or("missing fps")?;
+    match input.parse::<u64>() {
+        Ok(fps) if fps > 0 => Ok(Duration::from_micros(1_000_000 / fps)),
+        _ => Err(format!("invalid fps: {}", input)),
+    }
+}
+
+/// How many draw events a worker can get ahead of its canvas before it waits for them to be
+/// drawn.
+const EVENT_BACKLOG: usize = 1 << 16;
+
+/// A program running on its own worker thread, drawing into its own buffer.
+struct Canvas {
+    buffer: Buffer,
+    /// Where 0,0 is in the buffer
+    origin: (isize, isize),
+    rx: Receiver<Event>,
+    worker: JoinHandle<()>,
+    /// Set to stop the worker even if it isn't drawing
+    stopped: Arc<AtomicBool>,
+    terminated: bool,
+    /// The program's palette, for cycling
+    palette: Vec<u32>,
+    /// How many places the palette has been cycled
+    palette_shift: usize,
+    /// When to carry on drawing after a `WAIT`
+    paused_until: Option<Instant>,
+}
+
+impl Canvas {
+    fn spawn(input: &[u8], options: &Options) -> Result<Self, String> {
+        let (header, program) = decode(input).map_err(|e| e.to_string())?;
+
+        if options.strict {
+            // Registers given starting values with --set count as written
+            let lints: Vec<Lint> = lint(&program)
+                .into_iter()
+                .filter(|problem| {
+                    options.registers.is_none()
+                        || !matches!(problem, Lint::UninitializedRead { .. })
+                })
+                .collect();
+            for problem in &lints {
+                eprintln!("error: {}", problem);
+            }
+            if !lints.is_empty() {
+                return Err("program failed strict checks".to_string());
+            }
+        }
+
+        let mut vm = Vm::default()
+            .with_memory(header.memory)
+            .with_palette(header.palette.clone())
+            .with_logger(options.rendering.logger);
+        if let Some((uints, floats)) = options.registers {
+            vm = vm.with_registers(uints, floats);
+        }
+        if options.strict {
+            vm = vm.strict();
+        }
+        if options.profile {
+            vm = vm.with_profiling();
+        }
+        if options.debug {
+            vm = vm.with_debug();
+        }
+        if options.pixel_steps {
+            vm = vm.with_pixel_steps();
+        }
+        if options.dedup {
+            vm = vm.with_dedup();
+        }
+        if let Some(cycles) = options.watchdog {
+            vm = vm.with_watchdog(cycles);
+        }
+        if program.iter().any(|i| matches!(i, Instruction::Sample(_))) {
+            vm = vm.with_canvas(header.width, header.height, header.background);
+        }
+        let compiled = CompiledProgram::new(&program);
+
+        let width = header.width as usize;
+        let height = header.height as usize;
+
+        let trace = options.trace;
+        let stats = options.stats;
+        let mut logger = options.rendering.logger;
+        let (tx, rx) = sync_channel(EVENT_BACKLOG);
+        let stopped = Arc::new(AtomicBool::new(false));
+        let worker_stopped = Arc::clone(&stopped);
+        let worker = thread::spawn(move || {
+            while !vm.is_terminated() {
+                if worker_stopped.load(Ordering::Relaxed) {
+                    return;
+                }
+                let result = if trace {
+                    vm.step_with_hook(&compiled, &mut trace_instruction)
+                } else {
+                    vm.step(&compiled)
+                };
+                match result {
+                    Ok(events) => {
+                        for event in events {
+                            if trace && event.kind != PenState::Up {
+                                let (x, y) = event.pixel();
+                                eprintln!("trace:       pixel {} {} {:#08x}", x, y, event.color);
+                            }
+                            if tx.send(Event::Draw(*event)).is_err() {
+                                // The canvas was stopped, nothing will draw the rest
+                                return;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("error: {}", e);
+                        break;
+                    }
+                }
+            }
+            let _ = tx.send(Event::Terminated);
+            if stats {
+                eprintln!("stats: {}", vm.stats().to_json());
+            } else {
+                logger.log(Level::Info, "worker finished");
+            }
+
+            if let Some(profile) = vm.profile_report() {
+                print_profile(&program, profile);
+            }
+        });
+
+        let mut buffer = Buffer::new_with_background(width, height, header.background);
+        if options.cycle_palette {
+            buffer.track_palette();
+        }
+        let origin = options.origin.position(width, height);
+        draw_guides(&mut buffer, origin, options.grid, options.axes);
+
+        Ok(Canvas {
+            buffer,
+            origin,
+            rx,
+            worker,
+            stopped,
+            terminated: false,
+            palette: header.palette,
+            palette_shift: 0,
+            paused_until: None,
+        })
+    }
+
+    /// Draw everything the worker has sent since the last update, stopping early for a `WAIT`.
+    fn update(&mut self, rendering: Rendering) {
+        while !self.terminated {
+            if let Some(until) = self.paused_until {
+                if Instant::now() < until {
+                    break;
+                }
+                self.paused_until = None;
+            }
+            match self.rx.try_recv() {
+                Ok(event) => self.handle(event, rendering),
+                Err(_) => break,
+            }
+        }
+    }
+
+    /// Draw everything the worker sends until its program finishes, without waiting.
+    fn finish(&mut self, rendering: Rendering) {
+        while !self.terminated {
+            match self.rx.recv() {
+                Ok(event) => self.handle(event, rendering),
+                Err(_) => break,
+            }
+        }
+    }
+
+    /// Stop the worker, waiting for it to notice. A worker waiting for its events to be drawn
+    /// gives up once nothing is left to receive them.
+    fn stop(self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        drop(self.rx);
+        self.worker.join().unwrap();
+    }
+
+    /// Move every pixel drawn with a palette color one color further along the palette.
+    fn cycle_palette(&mut self) {
+        self.palette_shift += 1;
+        self.buffer.cycle_palette(&self.palette, self.palette_shift);
+    }
+
+    fn handle(&mut self, event: Event, rendering: Rendering) {
+        match event {
+            Event::Draw(event) if event.kind != PenState::Up => {
+                draw(&mut self.buffer, &mut self.origin, event, rendering)
+            }
+            Event::Draw(event) if event.wait > 0 => {
+                let wait = Duration::from_millis(u64::from(event.wait));
+                self.paused_until = Some(Instant::now() + wait);
+            }
+            Event::Draw(_) => {}
+            Event::Terminated => self.terminated = true,
+        }
+    }
+}
+
+/// Translucent gray, so guides show against dark and light backgrounds
+const GRID_COLOR: u32 = 0x3080_8080;
+const AXES_COLOR: u32 = 0x9080_8080;
+
+/// Draw gridlines `grid` pixels apart and the axes through `origin`, for lining drawings up with
+/// their coordinates. They're drawn first so the program draws over them, and don't extend into
+/// space added by `--grow`.
+fn draw_guides(buffer: &mut Buffer, origin: (isize, isize), grid: Option<usize>, axes: bool) {
+    let (right, bottom) = (buffer.width() as isize - 1, buffer.height() as isize - 1);
+
+    if let Some(spacing) = grid {
+        let spacing = spacing as isize;
+        for x in (origin.0.rem_euclid(spacing)..=right).step_by(spacing as usize) {
+            buffer.draw_line((x, 0), (x, bottom), GRID_COLOR);
+        }
+        for y in (origin.1.rem_euclid(spacing)..=bottom).step_by(spacing as usize) {
+            buffer.draw_line((0, y), (right, y), GRID_COLOR);
+        }
+    }
+
+    if axes {
+        buffer.draw_line((origin.0, 0), (origin.0, bottom), AXES_COLOR);
+        buffer.draw_line((0, origin.1), (right, origin.1), AXES_COLOR);
+    }
+}
+
+fn trace_instruction(pc: usize, instruction: &Instruction) {
+    eprintln!("trace: {:>5} {}", pc, instruction);
+}
+
+fn draw(buffer: &mut Buffer, origin: &mut (isize, isize), event: DrawEvent, rendering: Rendering) {
+    // Additive strokes are already soft, and fills have no size to smooth
+    let smooth = rendering.smooth
+        && event.kind == PenState::Down
+        && event.width > 0
+        && matches!(rendering.blend, BlendMode::Overwrite);
+    if smooth {
+        draw_smooth(buffer, origin, event, rendering.wrap);
+        return;
+    }
+
+    let (x, y) = event.pixel();
+
+    // Fills start from a single pixel however wide the pen is
+    let width = if event.kind == PenState::Fill {
+        1
+    } else {
+        event.width
+    };
+    for (dx, dy) in dot(width) {
+        draw_pixel(
+            buffer,
+            origin,
+            x.saturating_add(dx),
+            y.saturating_add(dy),
+            event,
+            rendering,
+        );
+    }
+}
+
+/// Draw `event` as an anti-aliased disc as wide as the pen, centered on its exact position.
+fn draw_smooth(buffer: &mut Buffer, origin: &mut (isize, isize), event: DrawEvent, wrap: WrapMode) {
+    let radius = f64::from(event.width) / 2.0;
+    // Whole positions land on a pixel's center, where a hard dot would draw
+    let mut cx = origin.0 as f64 + event.x + 0.5;
+    let mut cy = origin.1 as f64 + event.y + 0.5;
+
+    match wrap {
+        WrapMode::Clip => {}
+        // The disc's center wraps, but any part of it over the edge is clipped
+        WrapMode::Wrap => {
+            cx = cx.rem_euclid(buffer.width() as f64);
+            cy = cy.rem_euclid(buffer.height() as f64);
+        }
+        WrapMode::Grow => {
+            let reach = radius.ceil() as isize + 1;
+            for &(x, y) in &[(-reach, -reach), (reach, reach)] {
+                let (dx, dy) = buffer.ensure_contains(
+                    (cx as isize).saturating_add(x),
+                    (cy as isize).saturating_add(y),
+                );
+                origin.0 += dx as isize;
+                origin.1 += dy as isize;
+                cx += dx as f64;
+                cy += dy as f64;
+            }
+        }
+    }
+
+    buffer.draw_disc_aa(cx, cy, radius, event.color);
+}
+
+fn draw_pixel(
+    buffer: &mut Buffer,
+    origin: &mut (isize, isize),
+    x: isize,
+    y: isize,
+    event: DrawEvent,
+    rendering: Rendering,
+) {
+    let color = event.color;
+
+    let pixel = match rendering.wrap {
+        WrapMode::Clip => buffer.offset(*origin, x, y),
+        WrapMode::Wrap => {
+            let width = buffer.width() as isize;
+            let height = buffer.height() as isize;
+            let x = origin.0.saturating_add(x).rem_euclid(width);
+            let y = origin.1.saturating_add(y).rem_euclid(height);
+            Some((x as usize, y as usize))
+        }
+        WrapMode::Grow => {
+            let (dx, dy) =
+                buffer.ensure_contains(origin.0.saturating_add(x), origin.1.saturating_add(y));
+            origin.0 += dx as isize;
+            origin.1 += dy as isize;
+            // Pixels past the largest canvas are still outside it
+            buffer.offset(*origin, x, y)
+        }
+    };
+    let (x, y) = match pixel {
+        Some(pixel) => pixel,
+        None => return,
+    };
+
+    if event.kind == PenState::Fill {
+        buffer.flood_fill_with_index(x, y, color, event.palette_index);
+        return;
+    }
+
+    match rendering.blend {
+        BlendMode::Additive => buffer.add_pixel(x, y, color),
+        // An alpha of 0 is treated as opaque so plain 0xRRGGBB colors overwrite
+        BlendMode::Overwrite => match color >> 24 {
+            0x00 | 0xff => {
+                buffer.set_pixel(x, y, color);
+                buffer.set_palette_index(x, y, event.palette_index);
+            }
+            _ => buffer.blend_pixel(x, y, color),
+        },
+    }
+}
+
+/// Print the most executed instructions, hottest first.
+fn print_profile(program: &[Instruction], profile: &[u64]) {
+    let mut hottest: Vec<(usize, u64)> = profile.iter().copied().enumerate().collect();
+    hottest.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+
+    eprintln!("{:>12} {:>6}  instruction", "count", "pc");
+    for (pc, count) in hottest.into_iter().take(10).filter(|(_, count)| *count > 0) {
+        eprintln!("{:>12} {:>6}  {}", count, pc, program[pc]);
+    }
+}
+
+/// The size of the buffer needed to tile the canvases left to right.
+fn tiled_size(canvases: &[Canvas]) -> (usize, usize) {
+    let width = canvases.iter().map(|c| c.buffer.width()).sum();
+    let height = canvases
+        .iter()
+        .map(|c| c.buffer.height())
+        .max()
+        .unwrap_or(0);
+    (width, height)
+}
+
+/// How many pixels to buffer between flushes when streaming.
+const STREAM_FLUSH_INTERVAL: usize = 4096;
+
+/// Write every pixel drawn by each program, one program after another, to STDOUT as
+/// little-endian `x: i32, y: i32, color: u32` records. Coordinates are relative to the origin.
+fn stream(canvases: Vec<Canvas>) -> std::io::Result<()> {
+    let stdout = stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    let mut written = 0;
+
+    for canvas in canvases {
+        for event in canvas.rx.iter() {
+            let event = match event {
+                Event::Draw(event) if event.kind == PenState::Down => event,
+                Event::Draw(_) => continue,
+                Event::Terminated => break,
+            };
+
+            let (x, y) = event.pixel();
+            out.write_all(&(x as i32).to_le_bytes())?;
+            out.write_all(&(y as i32).to_le_bytes())?;
+            out.write_all(&event.color.to_le_bytes())?;
+
+            written += 1;
+            if written % STREAM_FLUSH_INTERVAL == 0 {
+                out.flush()?;
+            }
+        }
+
+        canvas.stop();
+    }
+
+    out.flush()
+}
+
+/// Run every program to completion and write the canvases side by side to `path` as a PPM.
+fn write_ppm(mut canvases: Vec<Canvas>, path: &str, rendering: Rendering) -> std::io::Result<()> {
+    for canvas in &mut canvases {
+        canvas.finish(rendering);
+    }
+
+    let (width, height) = tiled_size(&canvases);
+    let mut buffer = Buffer::new(width, height);
+    tile(&mut buffer, &canvases);
+
+    let mut out = BufWriter::new(File::create(path)?);
+    buffer.write_ppm(&mut out)?;
+    out.flush()?;
+
+    for canvas in canvases {
+        canvas.stop();
+    }
+    Ok(())
+}
+
+/// Copy each canvas into `buffer`, left to right.
+fn tile(buffer: &mut Buffer, canvases: &[Canvas]) {
+    let mut x = 0;
+    for canvas in canvases {
+        buffer.blit(&canvas.buffer, x, 0);
+        x += canvas.buffer.width();
+    }
+}
+
+/// How often `--watch` checks whether the source has changed.
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A source file assembled for `--watch`, tracking when it last changed.
+struct Watch {
+    path: String,
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+impl Watch {
+    fn new(path: String) -> Self {
+        Watch {
+            modified: Self::modified(&path),
+            path,
+            checked: Instant::now(),
+        }
+    }
+
+    fn modified(path: &str) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    /// Reassemble the source if it's changed since it was last checked, at most every
+    /// [`WATCH_INTERVAL`].
+    fn poll(&mut self) -> Option<Result<Vec<u8>, String>> {
+        if self.checked.elapsed() < WATCH_INTERVAL {
+            return None;
+        }
+        self.checked = Instant::now();
+
+        let modified = Self::modified(&self.path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        Some(self.assemble())
+    }
+
+    /// Assemble the source with the `assembler` next to this binary, which writes program.bin to
+    /// the current directory as it would when run by hand. Errors are the assembler's output,
+    /// ready to print.
+    fn assemble(&self) -> Result<Vec<u8>, String> {
+        let source = File::open(&self.path)
+            .map_err(|e| format!("error: unable to read {}: {}\n", self.path, e))?;
+        let assembler = std::env::current_exe()
+            .map(|exe| exe.with_file_name("assembler"))
+            .map_err(|e| format!("error: unable to find the assembler: {}\n", e))?;
+        let output = Command::new(&assembler)
+            .stdin(source)
+            .output()
+            .map_err(|e| format!("error: unable to run {}: {}\n", assembler.display(), e))?;
+
+        let messages = String::from_utf8_lossy(&output.stderr).into_owned();
+        if !output.status.success() {
+            return Err(messages);
+        }
+        // Warnings
+        eprint!("{}", messages);
+        std::fs::read("program.bin")
+            .map_err(|e| format!("error: unable to read program.bin: {}\n", e))
+    }
+}
+
+fn read_inputs(paths: &[String]) -> Vec<Vec<u8>> {
+    if paths.is_empty() {
+        let mut input = Vec::new();
+        stdin().read_to_end(&mut input).unwrap();
+        return vec![input];
+    }
+
+    paths
+        .iter()
+        .map(|path| {
+            std::fs::read(path).unwrap_or_else(|e| {
+                eprintln!("error: unable to read {}: {}", path, e);
+                std::process::exit(1);
+            })
+        })
+        .collect()
+}
+
+/// Parse whitespace separated hex bytes like `01 0a ff`, ignoring anything after a `#`.
+fn parse_hex(input: &[u8]) -> Result<Vec<u8>, String> {
+    let input = std::str::from_utf8(input).map_err(|_| "hex input isn't UTF-8".to_string())?;
+    let mut bytes = Vec::new();
+    for (number, line) in input.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or("");
+        for byte in code.split_whitespace() {
+            let parsed = if byte.len() <= 2 {
+                u8::from_str_radix(byte, 16).ok()
+            } else {
+                None
+            };
+            bytes.push(
+                parsed.ok_or_else(|| format!("line {}: not a hex byte: {}", number + 1, byte))?,
+            );
+        }
+    }
+    Ok(bytes)
+}
+
+/// How to run without a window, for when one can't be opened.
+const HEADLESS_HINT: &str = "use --ppm PATH or --stream to run without a window";
+
+/// Whether there's a display to open a window on. Only X11 and Wayland are checked for, other
+/// platforms are assumed to have one.
+fn has_display() -> bool {
+    if cfg!(all(unix, not(target_os = "macos"))) {
+        std::env::var_os("DISPLAY").is_some() || std::env::var_os("WAYLAND_DISPLAY").is_some()
+    } else {
+        true
+    }
+}
+
+fn main() {
+    let options = Options::parse().unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
     });
 
+    let mut watch = options.watch.clone().map(Watch::new);
+    let mut inputs = match &mut watch {
+        Some(watch) => vec![watch.assemble().unwrap_or_else(|e| {
+            eprint!("{}", e);
+            std::process::exit(1);
+        })],
+        None => read_inputs(&options.paths),
+    };
+    // Without a header there's no canvas size, so this can't be run as an empty program
+    if inputs.iter().any(|input| input.is_empty()) {
+        eprintln!("error: empty input, expected a program with at least a header");
+        std::process::exit(1);
+    }
+    if options.hex {
+        inputs = inputs
+            .iter()
+            .map(|input| parse_hex(input))
+            .collect::<Result<_, _>>()
+            .unwrap_or_else(|e| {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            });
+    }
+
+    if options.info {
+        for input in &inputs {
+            match program_stats(input) {
+                Ok(stats) => println!("{}", stats.to_json()),
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        return;
+    }
+
+    let mut canvases = inputs
+        .iter()
+        .map(|input| Canvas::spawn(input, &options))
+        .collect::<Result<Vec<Canvas>, _>>()
+        .unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        });
+
+    if options.stream {
+        if let Err(e) = stream(canvases) {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(path) = &options.ppm {
+        if let Err(e) = write_ppm(canvases, path, options.rendering) {
+            eprintln!("error: unable to write {}: {}", path, e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let (width, height) = tiled_size(&canvases);
     let mut buffer = Buffer::new(width, height);
 
+    if !has_display() {
+        eprintln!("error: no display available, {}", HEADLESS_HINT);
+        std::process::exit(1);
+    }
+
     let mut window = Window::new(
         "Drawer",
         width,
         height,
         WindowOptions {
-            scale: Scale::X1,
+            scale: options.scale,
             ..WindowOptions::default()
         },
     )
-    .expect("unable to initialize window");
+    .unwrap_or_else(|e| {
+        eprintln!("error: unable to open a window: {}, {}", e, HEADLESS_HINT);
+        std::process::exit(1);
+    });
 
-    // Limit to max ~60 fps update rate
-    window.limit_update_rate(Some(std::time::Duration::from_micros(16600)));
+    window.limit_update_rate(options.update_rate);
 
     let quit_on_vm_term = std::env::var("QUIT_ON_VM_TERM")
         .map(|_| true)
         .unwrap_or(false);
-    let mut terminated = false;
 
     while window.is_open() {
-        if quit_on_vm_term && terminated {
+        if quit_on_vm_term && canvases.iter().all(|c| c.terminated) {
             break;
         }
 
-        if !terminated {
-            for event in rx.try_iter() {
-                match event {
-                    Event::Pixel((x, y, color)) => {
-                        // We want 0,0 to be in the center of the buffer
-                        let x = (width as isize / 2) + x;
-                        let y = (height as isize / 2) + y;
-
-                        use std::convert::TryFrom;
-
-                        let x = usize::try_from(x);
-                        if x.is_err() {
-                            eprintln!("invalid x coordinate");
-                            break;
-                        }
-
-                        let y = usize::try_from(y);
-                        if y.is_err() {
-                            eprintln!("invalid y coordinate");
-                            break;
-                        }
-
-                        buffer.set_pixel(x.unwrap(), y.unwrap(), color);
-                    }
-                    Event::Terminated => {
-                        terminated = true;
-                        break;
+        // Keep drawing the last program that assembled until the source is fixed
+        match watch.as_mut().and_then(Watch::poll) {
+            Some(Ok(input)) => match Canvas::spawn(&input, &options) {
+                Ok(canvas) => {
+                    for canvas in std::mem::replace(&mut canvases, vec![canvas]) {
+                        canvas.stop();
                     }
                 }
+                Err(e) => eprintln!("error: {}", e),
+            },
+            Some(Err(e)) => eprint!("{}", e),
+            None => {}
+        }
+
+        for canvas in &mut canvases {
+            canvas.update(options.rendering);
+            if options.cycle_palette && canvas.terminated {
+                canvas.cycle_palette();
             }
         }
 
+        // Canvases can grow, in which case the window stretches the larger buffer to fit
+        let (width, height) = tiled_size(&canvases);
+        if (width, height) != (buffer.width(), buffer.height()) {
+            buffer = Buffer::new(width, height);
+        }
+
+        tile(&mut buffer, &canvases);
+
         window
-            .update_with_buffer(&buffer.buffer(), width, height)
+            .update_with_buffer(buffer.buffer(), width, height)
             .expect("unable to update buffer");
     }
 
-    worker.join().unwrap()
+    for canvas in canvases {
+        canvas.stop();
+    }
 }
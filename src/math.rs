@@ -0,0 +1,39 @@
+//! Float functions that aren't in `core`, from `std` when it's available and `libm` otherwise.
+
+#[cfg(feature = "std")]
+pub(crate) fn abs(x: f64) -> f64 {
+    x.abs()
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn ceil(x: f64) -> f64 {
+    x.ceil()
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn round(x: f64) -> f64 {
+    x.round()
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn sqrt(x: f64) -> f64 {
+    x.sqrt()
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn sin(x: f64) -> f64 {
+    x.sin()
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn cos(x: f64) -> f64 {
+    x.cos()
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn atan2(y: f64, x: f64) -> f64 {
+    y.atan2(x)
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) use libm::{atan2, ceil, cos, fabs as abs, round, sin, sqrt};
@@ -1,195 +1,1363 @@
-use crate::instruction::{FloatRegister, Instruction, Register, UintRegister, Value};
+use core::cmp::Ordering;
+use core::convert::TryFrom;
+use core::fmt;
+
+#[cfg(not(feature = "std"))]
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+use crate::buffer::Buffer;
+use crate::compile::{compare_floats, CompiledProgram, Op};
+use crate::instruction::{
+    AngleUnit, FloatRegister, Instruction, Register, UintRegister, Value, MAX_DIMENSION,
+};
+use crate::lint;
+use crate::log::{Level, Logger};
+use crate::math;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum PenState {
+    /// The turtle moved without drawing
+    Up,
+    /// The turtle drew at this position
+    Down,
+    /// Flood fill the area around this position
+    Fill,
+}
+
+/// Emitted by [`Vm::step`] when the turtle draws or moves. Coordinates are left unquantized so
+/// renderers can decide how to map them onto their canvas.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct DrawEvent {
+    pub x: f64,
+    pub y: f64,
+    pub color: u32,
+    pub kind: PenState,
+    /// How many pixels across to draw the dot, set by `PW`
+    pub width: u16,
+    /// The palette index `color` was selected with, if it was by `COLI`
+    pub palette_index: Option<u8>,
+    /// Milliseconds the renderer should pause for after this event, set by `WAIT`. Waits are
+    /// emitted as [`PenState::Up`] events so renderers that don't pace can ignore them.
+    pub wait: u16,
+}
+
+impl DrawEvent {
+    /// The event's position truncated to integer pixel coordinates.
+    pub fn pixel(&self) -> (isize, isize) {
+        (self.x as isize, self.y as isize)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum VmError {
+    /// `POP` was executed with nothing on the data stack
+    StackUnderflow { pc: usize },
+    /// `LDI` or `STI` was given an index that doesn't name a register
+    InvalidRegister { index: u16, pc: usize },
+    /// `LOAD` or `SAVE` was given an address past the end of memory
+    MemoryOutOfBounds { addr: u16, pc: usize },
+    /// `COLI` was given an index past the end of the palette
+    InvalidColorIndex { index: u16, pc: usize },
+    /// `DIV` was given a divisor of zero
+    DivideByZero { register: Register, pc: usize },
+    /// The watchdog saw `cycles` cycles pass without anything being drawn or any register
+    /// changing
+    NoProgress { cycles: usize, pc: usize },
+    /// `SEL` was given a number that isn't one of the [`TURTLES`]
+    InvalidTurtle { index: u16, pc: usize },
+    /// `JMPR` was given an instruction index past the end of the program
+    JumpOutOfRange { target: u16, pc: usize },
+    /// In strict mode, `register` was read before anything wrote it
+    UninitializedRead { register: Register, pc: usize },
+    /// [`Limits::max_cycles`] instructions ran without the program halting
+    CycleLimit { limit: usize, pc: usize },
+    /// More than [`Limits::max_pixels`] draw and fill events were emitted
+    PixelLimit { limit: usize, pc: usize },
+    /// The program has `words` words of memory, more than [`Limits::max_memory`]
+    MemoryLimit { words: usize, limit: usize },
+    /// The data stack grew past [`Limits::max_stack_depth`] values
+    StackLimit { limit: usize, pc: usize },
+    /// `SAMPLE` was executed by a VM without a canvas, see [`Vm::with_canvas`]
+    NoCanvas { pc: usize },
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VmError::StackUnderflow { pc } => write!(f, "stack underflow at {}", pc),
+            VmError::InvalidRegister { index, pc } => {
+                write!(f, "invalid register index {} at {}", index, pc)
+            }
+            VmError::MemoryOutOfBounds { addr, pc } => {
+                write!(f, "memory address {} out of bounds at {}", addr, pc)
+            }
+            VmError::InvalidColorIndex { index, pc } => {
+                write!(f, "invalid palette index {} at {}", index, pc)
+            }
+            VmError::DivideByZero { register, pc } => {
+                write!(f, "division of {} by zero at {}", register, pc)
+            }
+            VmError::NoProgress { cycles, pc } => {
+                write!(f, "no progress in {} cycles at {}", cycles, pc)
+            }
+            VmError::InvalidTurtle { index, pc } => {
+                write!(f, "invalid turtle {} at {}", index, pc)
+            }
+            VmError::JumpOutOfRange { target, pc } => {
+                write!(
+                    f,
+                    "jump to {} past the end of the program at {}",
+                    target, pc
+                )
+            }
+            VmError::UninitializedRead { register, pc } => {
+                write!(f, "{} read before it was written at {}", register, pc)
+            }
+            VmError::CycleLimit { limit, pc } => {
+                write!(f, "cycle limit of {} reached at {}", limit, pc)
+            }
+            VmError::PixelLimit { limit, pc } => {
+                write!(f, "pixel limit of {} reached at {}", limit, pc)
+            }
+            VmError::MemoryLimit { words, limit } => {
+                write!(f, "{} words of memory over the limit of {}", words, limit)
+            }
+            VmError::StackLimit { limit, pc } => {
+                write!(f, "stack limit of {} reached at {}", limit, pc)
+            }
+            VmError::NoCanvas { pc } => write!(f, "nothing to sample without a canvas at {}", pc),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for VmError {}
+
+/// Bounds on what a program can use during [`Vm::run`], for running programs that can't be
+/// trusted to halt or stay small. `None` is unlimited, and going over a limit stops the run with
+/// its [`VmError`].
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
+pub struct Limits {
+    /// Instructions executed
+    pub max_cycles: Option<usize>,
+    /// Draw and fill events emitted, counted like [`RunStats::pixels_drawn`]
+    pub max_pixels: Option<usize>,
+    /// Words of memory, as sized by the program header
+    pub max_memory: Option<usize>,
+    /// Values on the data stack
+    pub max_stack_depth: Option<usize>,
+}
+
+impl Limits {
+    /// Limit only the number of instructions executed.
+    pub fn cycles(max_cycles: usize) -> Self {
+        Limits {
+            max_cycles: Some(max_cycles),
+            ..Limits::default()
+        }
+    }
+}
+
+/// The extent of everything drawn so far, so exporters can crop to the drawing.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Bounds {
+    pub min_x: f64,
+    pub min_y: f64,
+    pub max_x: f64,
+    pub max_y: f64,
+}
+
+impl Bounds {
+    fn include(&mut self, x: f64, y: f64) {
+        self.min_x = self.min_x.min(x);
+        self.min_y = self.min_y.min(y);
+        self.max_x = self.max_x.max(x);
+        self.max_y = self.max_y.max(y);
+    }
+}
+
+/// A summary of a run, from [`Vm::stats`].
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct RunStats {
+    /// Instructions executed
+    pub cycles: usize,
+    /// Draw and fill events emitted, counting a pixel each time it's drawn
+    pub pixels_drawn: usize,
+    /// The extent of everything drawn, `None` if nothing was
+    pub bounds: Option<Bounds>,
+    /// Whether the program reached `HLT`
+    pub halted: bool,
+}
+
+impl RunStats {
+    /// The stats as a single line JSON object.
+    pub fn to_json(&self) -> String {
+        let bounds = match self.bounds {
+            Some(b) => format!(
+                "{{\"min_x\":{},\"min_y\":{},\"max_x\":{},\"max_y\":{}}}",
+                b.min_x, b.min_y, b.max_x, b.max_y
+            ),
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"cycles\":{},\"pixels_drawn\":{},\"bounds\":{},\"halted\":{}}}",
+            self.cycles, self.pixels_drawn, bounds, self.halted
+        )
+    }
+}
+
+/// How many turtles `SEL` can switch between.
+pub const TURTLES: usize = 8;
+
+/// The most points a single `ARC`, `POLY` or `SPIRAL` emits, so a huge size from a register
+/// can't run away with memory inside one step. Bigger shapes have their points spread further
+/// apart.
+pub const MAX_SHAPE_POINTS: usize = 1 << 20;
+
+/// The state of a turtle other than the selected one, whose state is in the VM's registers and
+/// pen fields.
+#[derive(Default, Copy, Clone)]
+struct Turtle {
+    angle: u16,
+    x: f64,
+    y: f64,
+    draw: bool,
+    color_ramp: Option<u16>,
+    pen_color: Option<u32>,
+    pen_index: Option<u8>,
+}
+
+/// Checks every `limit` cycles that the program has drawn something or changed a register.
+struct Watchdog {
+    limit: usize,
+    /// The cycle count at the last check
+    checked_at: usize,
+    /// The registers at the last check, floats as bits so NaNs compare equal
+    registers: ([u16; 8], [u64; 8]),
+    /// Whether anything has been drawn since the last check
+    drew: bool,
+}
 
 #[derive(Default)]
 pub struct Vm {
     pc: usize,
     draw: bool,
     terminated: bool,
+    cycles: usize,
+    /// When set, drawn pixels are colored by hue, completing a full turn every `n` cycles.
+    color_ramp: Option<u16>,
+    /// Colors from the program header, selected with `COLI`
+    palette: Vec<u32>,
+    /// The palette color selected by `COLI`, white until one is
+    pen_color: Option<u32>,
+    /// The index of `pen_color` in the palette, `None` when it was set by `RGB`
+    pen_index: Option<u8>,
+    /// How many pixels across each drawn dot is, set by `PW`
+    pen_width: u16,
+    /// How many pixels to draw then skip, set by `DASH`. Lines are solid when `None`.
+    dash_pattern: Option<[u16; 2]>,
+    /// How many pixels into the dash pattern the pen is
+    dash_phase: u32,
+    /// How many rotated copies of each draw event to emit, and whether to reflect them too, set
+    /// by `MIRROR`
+    symmetry: Option<(u16, bool)>,
+    angle_unit: AngleUnit,
+    bounds: Option<Bounds>,
+    /// Values saved by `PUSH`, tagged with the kind of register they came from
+    data_stack: Vec<Value>,
+    /// How the register compared to the value at the last `CMP`, `None` if nothing has been
+    /// compared yet or the comparison involved NaN
+    flags: Option<Ordering>,
+    /// Words read and written by `LOAD` and `SAVE`, sized by the program header
+    memory: Vec<u16>,
+    /// How many times each instruction has been executed, when profiling
+    profile: Option<Vec<u64>>,
+    /// Suppress overflow warnings
+    quiet: bool,
+    /// Where warnings and `DBG` output go, STDERR when `None` and there is one
+    logger: Option<Box<dyn Logger>>,
+    /// Print registers when `DBG` executes
+    debug_enabled: bool,
+    /// Only draw when the pen reaches a new pixel, filling in any it jumped over
+    pixel_steps: bool,
+    /// The pixel the pen last drew, when it hasn't been lifted since
+    last_emitted: Option<(isize, isize)>,
+    /// Drop events that repeat the one before
+    dedup: bool,
+    /// The last event recorded, for `dedup`
+    last_recorded: Option<DrawEvent>,
+    watchdog: Option<Watchdog>,
+    /// Stop on reads of registers that haven't been written
+    strict: bool,
+    /// The registers written so far other than `A`, `X` and `Y`, as bits from [`lint::bit`]
+    written: u16,
+    /// The turtle `SEL` last selected
+    turtle: usize,
+    /// Saved state for each turtle, the selected one's is stale
+    turtles: [Turtle; TURTLES],
+    /// How many draw and fill events have been emitted
+    pixels_drawn: usize,
+    /// Stop recording events once more than this many pixels are drawn, from [`Vm::run`]'s
+    /// limits
+    pixel_limit: Option<usize>,
+    /// Draw events emitted by the current step
+    events: Vec<DrawEvent>,
+    /// Everything drawn so far, for `SAMPLE`
+    canvas: Option<Buffer>,
     uint_registers: [u16; 8],
     float_registers: [f64; 8],
 }
 
 impl Vm {
-    pub fn step(&mut self, program: &[Instruction]) -> Option<(isize, isize, u32)> {
-        match program[self.pc] {
-            Instruction::Draw => {
-                self.draw = !self.draw;
-            }
-            Instruction::Forward => {
-                let angle = (self.uint_registers[UintRegister::A as usize] % 360) as f64;
-                let radians = angle.to_radians();
-                self.float_registers[FloatRegister::X as usize] += radians.cos();
-                self.float_registers[FloatRegister::Y as usize] += radians.sin();
-            }
-            Instruction::Halt => self.terminated = true,
-            Instruction::Add(register, value) => match register {
-                Register::UintRegister(register) => {
-                    let value = self.unwrap_uint_value(value);
-                    let (value, overflowed) =
-                        self.uint_registers[register as usize].overflowing_add(value);
-                    if overflowed {
-                        eprintln!("warning: {:?} overflowed", register);
-                    }
-                    self.uint_registers[register as usize] = value;
-                }
-                Register::FloatRegister(register) => {
-                    self.float_registers[register as usize] += self.unwrap_float_value(value);
+    /// Count how many times each instruction executes, see [`Vm::profile_report`].
+    pub fn with_profiling(mut self) -> Self {
+        self.profile = Some(Vec::new());
+        self
+    }
+
+    /// Give the VM `size` words of zeroed memory.
+    pub fn with_memory(mut self, size: u16) -> Self {
+        self.memory = vec![0; size as usize];
+        self
+    }
+
+    /// Give the VM the palette `COLI` selects colors from.
+    pub fn with_palette(mut self, palette: Vec<u32>) -> Self {
+        self.palette = palette;
+        self
+    }
+
+    /// Start the VM with these register values instead of zero, `A` to `H` then `S` to `Z`.
+    pub fn with_registers(mut self, uints: [u16; 8], floats: [f64; 8]) -> Self {
+        self.uint_registers = uints;
+        self.float_registers = floats;
+        self.written = u16::MAX;
+        self
+    }
+
+    /// Stop with [`VmError::UninitializedRead`] when an instruction reads a register nothing has
+    /// written, other than `A`, `X` and `Y`. See [`lint`](crate::lint::lint) to find these
+    /// before running.
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// Stop the VM printing a warning whenever a register overflows.
+    pub fn quiet(mut self) -> Self {
+        self.quiet = true;
+        self
+    }
+
+    /// Send warnings and `DBG` output to `logger` rather than STDERR. Without `std` they're
+    /// dropped unless there's a logger.
+    pub fn with_logger(mut self, logger: impl Logger + 'static) -> Self {
+        self.logger = Some(Box::new(logger));
+        self
+    }
+
+    /// Have `DBG` print the registers to STDERR. Without this `DBG` does nothing.
+    pub fn with_debug(mut self) -> Self {
+        self.debug_enabled = true;
+        self
+    }
+
+    /// Stop with [`VmError::NoProgress`] if `cycles` pass without anything being drawn or any
+    /// register changing, for catching programs stuck in a loop. A limit of 0 never stops.
+    pub fn with_watchdog(mut self, cycles: usize) -> Self {
+        self.watchdog = match cycles {
+            0 => None,
+            limit => Some(Watchdog {
+                limit,
+                checked_at: self.cycles,
+                registers: self.register_snapshot(),
+                drew: false,
+            }),
+        };
+        self
+    }
+
+    /// Draw each pixel the pen passes through exactly once, rather than drawing wherever the pen
+    /// is after every instruction. Moves of less than a pixel draw nothing, and longer ones fill
+    /// in the pixels between.
+    pub fn with_pixel_steps(mut self) -> Self {
+        self.pixel_steps = true;
+        self
+    }
+
+    /// Draw into a `width` by `height` canvas of its own as the program runs, so `SAMPLE` can read
+    /// it back. Programs that don't sample don't need one.
+    pub fn with_canvas(mut self, width: u16, height: u16, background: u32) -> Self {
+        self.canvas = Some(Buffer::new_with_background(
+            usize::from(width),
+            usize::from(height),
+            background,
+        ));
+        self
+    }
+
+    /// Skip any event that draws the same pixel the same way as the event just before it, as
+    /// happens when the turtle moves less than a pixel. This shrinks exported and streamed output,
+    /// but changes the drawing when translucent or additive pixels build up.
+    pub fn with_dedup(mut self) -> Self {
+        self.dedup = true;
+        self
+    }
+
+    /// Return the VM to the state it started in, so it can run a program again without being
+    /// rebuilt. Registers, memory, the stacks, the pen and every turtle go back to zero, while
+    /// the palette, the memory size and options like [`Vm::quiet`] and [`Vm::with_watchdog`] are
+    /// kept. Registers set with [`Vm::with_registers`] are zeroed too.
+    pub fn reset(&mut self) {
+        let mut memory = core::mem::take(&mut self.memory);
+        memory.fill(0);
+        let mut profile = self.profile.take();
+        if let Some(profile) = &mut profile {
+            profile.fill(0);
+        }
+        let mut canvas = self.canvas.take();
+        if let Some(canvas) = &mut canvas {
+            canvas.clear();
+        }
+        let mut data_stack = core::mem::take(&mut self.data_stack);
+        data_stack.clear();
+        let mut events = core::mem::take(&mut self.events);
+        events.clear();
+        let watchdog = self.watchdog.as_ref().map_or(0, |watchdog| watchdog.limit);
+
+        *self = Vm {
+            palette: core::mem::take(&mut self.palette),
+            memory,
+            profile,
+            canvas,
+            data_stack,
+            events,
+            quiet: self.quiet,
+            logger: self.logger.take(),
+            debug_enabled: self.debug_enabled,
+            pixel_steps: self.pixel_steps,
+            dedup: self.dedup,
+            strict: self.strict,
+            ..Vm::default()
+        }
+        .with_watchdog(watchdog);
+    }
+
+    /// Execute the instruction at the program counter. Running past the end of the program halts.
+    pub fn step(&mut self, program: &CompiledProgram) -> Result<&[DrawEvent], VmError> {
+        self.events.clear();
+
+        // Running off the end halts, so an empty program does nothing
+        if self.pc >= program.len() {
+            self.terminated = true;
+            return Ok(&self.events);
+        }
+
+        self.cycles += 1;
+        self.check_progress()?;
+
+        if let Some(profile) = &mut self.profile {
+            // The counts grow to fit whatever program is run
+            if profile.len() < program.len() {
+                profile.resize(program.len(), 0);
+            }
+            profile[self.pc] += 1;
+        }
+
+        if self.strict {
+            self.check_initialized(program.instruction(self.pc))?;
+        }
+
+        // Set when the instruction changed the position, so pen-up moves can be reported too
+        let mut moved = false;
+
+        match *program.op(self.pc) {
+            Op::JumpUint {
+                register,
+                condition,
+                value,
+                target,
+            } => {
+                let value = self.unwrap_uint_value(value);
+                if condition.holds(Some(self.uint_registers[register].cmp(&value))) {
+                    self.pc = target;
+                    return Ok(&self.events);
                 }
-            },
-            Instruction::Sub(register, value) => match register {
-                Register::UintRegister(register) => {
-                    let value = self.unwrap_uint_value(value);
-                    let (value, overflowed) =
-                        self.uint_registers[register as usize].overflowing_sub(value);
-                    if overflowed {
-                        eprintln!("warning: {:?} overflowed", register);
+            }
+            Op::JumpFloat {
+                register,
+                condition,
+                value,
+                target,
+            } => {
+                let value = self.unwrap_float_value(value);
+                if condition.holds(compare_floats(self.float_registers[register], value)) {
+                    self.pc = target;
+                    return Ok(&self.events);
+                }
+            }
+            Op::JumpFlag { condition, target } => {
+                if condition.holds(self.flags) {
+                    self.pc = target;
+                    return Ok(&self.events);
+                }
+            }
+            Op::Instruction(instruction) => match instruction {
+                Instruction::JumpRegister(register) => {
+                    let target = self.unwrap_uint_value(Value::Register(register));
+                    if usize::from(target) >= program.len() {
+                        return Err(VmError::JumpOutOfRange {
+                            target,
+                            pc: self.pc,
+                        });
                     }
-                    self.uint_registers[register as usize] = value;
+                    self.pc = usize::from(target);
+                    return Ok(&self.events);
                 }
-                Register::FloatRegister(register) => {
-                    self.float_registers[register as usize] -= self.unwrap_float_value(value);
+                Instruction::Draw => {
+                    self.draw = !self.draw;
                 }
-            },
-            Instruction::Store(r1, value) => match r1 {
-                Register::UintRegister(r1) => {
-                    self.uint_registers[r1 as usize] = self.unwrap_uint_value(value);
+                Instruction::Forward => {
+                    let radians = self.heading();
+                    self.float_registers[FloatRegister::X as usize] += math::cos(radians);
+                    self.float_registers[FloatRegister::Y as usize] += math::sin(radians);
+                    moved = true;
                 }
-                Register::FloatRegister(r1) => {
-                    self.float_registers[r1 as usize] = self.unwrap_float_value(value);
+                Instruction::MoveBy(dx, dy) => {
+                    let dx = self.unwrap_float_value(Value::Register(dx));
+                    let dy = self.unwrap_float_value(Value::Register(dy));
+                    self.float_registers[FloatRegister::X as usize] += dx;
+                    self.float_registers[FloatRegister::Y as usize] += dy;
+                    moved = true;
                 }
-            },
-            Instruction::Increment(register) => match register {
-                Register::UintRegister(register) => {
-                    let (value, overflowed) =
-                        self.uint_registers[register as usize].overflowing_add(1);
-                    if overflowed {
-                        eprintln!("warning: {:?} overflowed", register);
+                Instruction::Halt => self.terminated = true,
+                Instruction::Fill => self.emit(self.draw_event(PenState::Fill)),
+                Instruction::Compare(register, value) => {
+                    self.flags = match register {
+                        Register::UintRegister(r) => Some(
+                            self.uint_registers[r as usize].cmp(&self.unwrap_uint_value(value)),
+                        ),
+                        Register::FloatRegister(r) => compare_floats(
+                            self.float_registers[r as usize],
+                            self.unwrap_float_value(value),
+                        ),
+                    };
+                }
+                Instruction::Plot => {
+                    // When drawing is on the pixel is drawn below like any other step
+                    if !self.draw {
+                        self.emit(self.draw_event(PenState::Down));
                     }
-                    self.uint_registers[register as usize] = value;
                 }
-                Register::FloatRegister(register) => {
-                    self.float_registers[register as usize] += 1.0;
+                Instruction::Debug => self.print_registers(),
+                Instruction::Home => {
+                    self.float_registers[FloatRegister::X as usize] = 0.0;
+                    self.float_registers[FloatRegister::Y as usize] = 0.0;
+                    self.uint_registers[UintRegister::A as usize] = 0;
+                    moved = true;
                 }
-            },
-            Instruction::Decrement(register) => match register {
-                Register::UintRegister(register) => {
-                    let (value, overflowed) =
-                        self.uint_registers[register as usize].overflowing_sub(1);
-                    if overflowed {
-                        eprintln!("warning: {:?} overflowed", register);
+                Instruction::Add(register, value) => match register {
+                    Register::UintRegister(register) => {
+                        let value = self.unwrap_uint_value(value);
+                        let (value, overflowed) =
+                            self.uint_registers[register as usize].overflowing_add(value);
+                        if overflowed {
+                            self.warn_overflow(register);
+                        }
+                        self.uint_registers[register as usize] = value;
+                    }
+                    Register::FloatRegister(register) => {
+                        self.float_registers[register as usize] += self.unwrap_float_value(value);
+                    }
+                },
+                Instruction::Sub(register, value) => match register {
+                    Register::UintRegister(register) => {
+                        let value = self.unwrap_uint_value(value);
+                        let (value, overflowed) =
+                            self.uint_registers[register as usize].overflowing_sub(value);
+                        if overflowed {
+                            self.warn_overflow(register);
+                        }
+                        self.uint_registers[register as usize] = value;
+                    }
+                    Register::FloatRegister(register) => {
+                        self.float_registers[register as usize] -= self.unwrap_float_value(value);
+                    }
+                },
+                Instruction::Store(r1, value) => self.set_register(r1, value),
+                Instruction::Increment(register) => match register {
+                    Register::UintRegister(register) => {
+                        let (value, overflowed) =
+                            self.uint_registers[register as usize].overflowing_add(1);
+                        if overflowed {
+                            self.warn_overflow(register);
+                        }
+                        self.uint_registers[register as usize] = value;
+                    }
+                    Register::FloatRegister(register) => {
+                        self.float_registers[register as usize] += 1.0;
+                    }
+                },
+                Instruction::Decrement(register) => match register {
+                    Register::UintRegister(register) => {
+                        let (value, overflowed) =
+                            self.uint_registers[register as usize].overflowing_sub(1);
+                        if overflowed {
+                            self.warn_overflow(register);
+                        }
+                        self.uint_registers[register as usize] = value;
+                    }
+                    Register::FloatRegister(register) => {
+                        self.float_registers[register as usize] -= 1.0;
+                    }
+                },
+                Instruction::Multiply(register, value) => match register {
+                    Register::UintRegister(register) => {
+                        let value = self.unwrap_uint_value(value);
+                        let (value, overflowed) =
+                            self.uint_registers[register as usize].overflowing_mul(value);
+                        if overflowed {
+                            self.warn_overflow(register);
+                        }
+                        self.uint_registers[register as usize] = value;
+                    }
+                    Register::FloatRegister(register) => {
+                        let value = self.unwrap_float_value(value);
+                        self.float_registers[register as usize] *= value;
+                    }
+                },
+                Instruction::Divide(register, value) => {
+                    let divide_by_zero = VmError::DivideByZero {
+                        register,
+                        pc: self.pc,
+                    };
+                    match register {
+                        Register::UintRegister(register) => {
+                            let value = self.unwrap_uint_value(value);
+                            if value == 0 {
+                                return Err(divide_by_zero);
+                            }
+                            self.uint_registers[register as usize] /= value;
+                        }
+                        Register::FloatRegister(register) => {
+                            let value = self.unwrap_float_value(value);
+                            if value == 0.0 {
+                                return Err(divide_by_zero);
+                            }
+                            self.float_registers[register as usize] /= value;
+                        }
                     }
-                    self.uint_registers[register as usize] = value;
                 }
-                Register::FloatRegister(register) => {
-                    self.float_registers[register as usize] -= 1.0;
+                Instruction::Gradient(value) => {
+                    self.color_ramp = match self.unwrap_uint_value(value) {
+                        0 => None,
+                        period => Some(period),
+                    };
                 }
-            },
-            Instruction::JumpIfNonZero(register, addr) => {
-                if self.check_conditional(
-                    register,
-                    Value::Uint(0),
-                    |a: f64, b: f64| (a - b).abs() > f64::EPSILON, // a != b
-                ) {
-                    self.pc = addr.into();
-                    return None;
-                }
-            }
-            Instruction::JumpIfEqual(register, value, addr) => {
-                if self.check_conditional(
-                    register,
-                    value,
-                    |a, b| (a - b).abs() < f64::EPSILON, // a == b
-                ) {
-                    self.pc = addr.into();
-                    return None;
-                }
-            }
-            Instruction::JumpIfNotEqual(register, value, addr) => {
-                if self.check_conditional(
-                    register,
-                    value,
-                    |a, b| (a - b).abs() > f64::EPSILON, // a != b
-                ) {
-                    self.pc = addr.into();
-                    return None;
-                }
-            }
-            Instruction::JumpIfGreaterThan(register, value, addr) => {
-                if self.check_conditional(register, value, |a, b| a > b) {
-                    self.pc = addr.into();
-                    return None;
-                }
-            }
-            Instruction::JumpIfLessThan(register, value, addr) => {
-                if self.check_conditional(register, value, |a, b| a < b) {
-                    self.pc = addr.into();
-                    return None;
-                }
-            }
-            Instruction::Multiply(register, value) => match register {
-                Register::UintRegister(register) => {
-                    let value = self.unwrap_uint_value(value);
-                    let (value, overflowed) =
-                        self.uint_registers[register as usize].overflowing_mul(value);
-                    if overflowed {
-                        eprintln!("warning: {:?} overflowed", register);
-                    }
-                    self.uint_registers[register as usize] = value;
-                }
-                Register::FloatRegister(register) => {
-                    let value = self.unwrap_float_value(value);
-                    self.float_registers[register as usize] *= value;
+                Instruction::ColorIndex(value) => {
+                    let index = self.unwrap_uint_value(value);
+                    let color = self
+                        .palette
+                        .get(usize::from(index))
+                        .ok_or(VmError::InvalidColorIndex { index, pc: self.pc })?;
+                    self.pen_color = Some(*color);
+                    self.pen_index = Some(index as u8);
+                    self.color_ramp = None;
                 }
-            },
-            Instruction::Divide(register, value) => match register {
-                Register::UintRegister(register) => {
-                    let value = self.unwrap_uint_value(value);
-                    let (value, overflowed) =
-                        self.uint_registers[register as usize].overflowing_div(value);
-                    if overflowed {
-                        eprintln!("warning: {:?} overflowed", register);
+                Instruction::Mirror(ways, reflect) => {
+                    let ways = self.unwrap_uint_value(ways);
+                    let reflect = self.unwrap_float_value(reflect) != 0.0;
+                    self.symmetry = if ways <= 1 && !reflect {
+                        None
+                    } else {
+                        Some((ways.max(1), reflect))
+                    };
+                }
+                Instruction::Dash(on, off) => {
+                    let pattern = [self.unwrap_uint_value(on), self.unwrap_uint_value(off)];
+                    self.dash_pattern = if pattern == [0, 0] {
+                        None
+                    } else {
+                        Some(pattern)
+                    };
+                    self.dash_phase = 0;
+                }
+                Instruction::Heading(x, y) => {
+                    let x = self.unwrap_float_value(Value::Register(x));
+                    let y = self.unwrap_float_value(Value::Register(y));
+                    self.set_heading(math::atan2(y, x));
+                }
+                Instruction::Sample(register) => {
+                    let canvas = self
+                        .canvas
+                        .as_ref()
+                        .ok_or(VmError::NoCanvas { pc: self.pc })?;
+                    let (x, y) = self.draw_event(PenState::Up).pixel();
+                    let color = canvas
+                        .pixel_centered(x, y)
+                        .unwrap_or_else(|| canvas.background())
+                        & 0xff_ffff;
+                    match register {
+                        Register::UintRegister(r) => {
+                            self.uint_registers[r as usize] = rgb565(color);
+                        }
+                        Register::FloatRegister(r) => {
+                            self.float_registers[r as usize] = f64::from(color);
+                        }
                     }
-                    self.uint_registers[register as usize] = value;
                 }
-                Register::FloatRegister(register) => {
-                    let value = self.unwrap_float_value(value);
-                    self.float_registers[register as usize] /= value;
+                Instruction::Convert(register, from) => {
+                    let value = match from {
+                        Register::FloatRegister(r) => {
+                            Value::Float(math::round(self.float_registers[r as usize]))
+                        }
+                        Register::UintRegister(_) => Value::Register(from),
+                    };
+                    self.set_register(register, value);
+                }
+                Instruction::Rgb(r, g, b) => {
+                    let channel =
+                        |vm: &Self, r| u32::from(vm.unwrap_uint_value(Value::Register(r)) % 256);
+                    self.pen_color =
+                        Some(channel(self, r) << 16 | channel(self, g) << 8 | channel(self, b));
+                    self.pen_index = None;
+                    self.color_ramp = None;
+                }
+                Instruction::PenWidth(value) => {
+                    // A dot wider than the largest canvas draws nothing more
+                    self.pen_width = self.unwrap_uint_value(value).min(MAX_DIMENSION)
+                }
+                Instruction::Select(value) => {
+                    let index = self.unwrap_uint_value(value);
+                    if usize::from(index) >= TURTLES {
+                        return Err(VmError::InvalidTurtle { index, pc: self.pc });
+                    }
+                    self.select(usize::from(index));
+                    moved = true;
                 }
+                Instruction::Wait(value) => {
+                    // Recorded directly so MIRROR doesn't repeat it
+                    let wait = self.unwrap_uint_value(value);
+                    self.record(DrawEvent {
+                        wait,
+                        ..self.draw_event(PenState::Up)
+                    });
+                }
+                Instruction::Time(register) => match register {
+                    Register::UintRegister(r) => {
+                        self.uint_registers[r as usize] = self.cycles as u16
+                    }
+                    Register::FloatRegister(r) => {
+                        self.float_registers[r as usize] = self.cycles as f64
+                    }
+                },
+                Instruction::Min(register, value) => match register {
+                    Register::UintRegister(register) => {
+                        let value = self.unwrap_uint_value(value);
+                        self.uint_registers[register as usize] =
+                            self.uint_registers[register as usize].min(value);
+                    }
+                    Register::FloatRegister(register) => {
+                        let value = self.unwrap_float_value(value);
+                        self.float_registers[register as usize] =
+                            self.float_registers[register as usize].min(value);
+                    }
+                },
+                Instruction::Max(register, value) => match register {
+                    Register::UintRegister(register) => {
+                        let value = self.unwrap_uint_value(value);
+                        self.uint_registers[register as usize] =
+                            self.uint_registers[register as usize].max(value);
+                    }
+                    Register::FloatRegister(register) => {
+                        let value = self.unwrap_float_value(value);
+                        self.float_registers[register as usize] =
+                            self.float_registers[register as usize].max(value);
+                    }
+                },
+                Instruction::AngleUnit(unit) => self.angle_unit = unit,
+                Instruction::Push(register) => {
+                    let value = match register {
+                        Register::UintRegister(r) => Value::Uint(self.uint_registers[r as usize]),
+                        Register::FloatRegister(r) => {
+                            Value::Float(self.float_registers[r as usize])
+                        }
+                    };
+                    self.data_stack.push(value);
+                }
+                Instruction::Pop(register) => {
+                    let value = self
+                        .data_stack
+                        .pop()
+                        .ok_or(VmError::StackUnderflow { pc: self.pc })?;
+                    self.set_register(register, value);
+                }
+                Instruction::LoadIndirect(register, index) => {
+                    let source = self.indirect_register(index)?;
+                    if self.strict && !self.is_written(source) {
+                        return Err(VmError::UninitializedRead {
+                            register: source,
+                            pc: self.pc,
+                        });
+                    }
+                    self.set_register(register, Value::Register(source));
+                }
+                Instruction::StoreIndirect(index, register) => {
+                    let target = self.indirect_register(index)?;
+                    self.set_register(target, Value::Register(register));
+                    self.written |= lint::bit(target);
+                }
+                Instruction::Load(addr, register) => {
+                    let addr = self.memory_address(addr)?;
+                    self.set_register(register, Value::Uint(self.memory[addr]));
+                }
+                Instruction::Save(addr, register) => {
+                    let addr = self.memory_address(addr)?;
+                    self.memory[addr] = self.unwrap_uint_value(Value::Register(register));
+                }
+                Instruction::Arc(radius, sweep) => {
+                    if self.draw {
+                        let radius = self.unwrap_float_value(radius);
+                        let sweep = self.unwrap_float_value(sweep).to_radians();
+                        self.arc(radius, sweep);
+                    }
+                }
+                Instruction::Poly(sides, length) => {
+                    if self.draw {
+                        let sides = self.unwrap_uint_value(sides);
+                        let length = self.unwrap_float_value(length);
+                        self.poly(sides, length);
+                    }
+                }
+                Instruction::Spiral(turns, growth) => {
+                    if self.draw {
+                        let turns = self.unwrap_uint_value(turns);
+                        let growth = self.unwrap_float_value(growth);
+                        self.spiral(turns, growth);
+                    }
+                }
+                Instruction::Abs(register) => match register {
+                    Register::UintRegister(_) => {}
+                    Register::FloatRegister(register) => {
+                        self.float_registers[register as usize] =
+                            math::abs(self.float_registers[register as usize]);
+                    }
+                },
+                Instruction::JumpIfNonZero(..)
+                | Instruction::JumpIfEqual(..)
+                | Instruction::JumpIfNotEqual(..)
+                | Instruction::JumpIfGreaterThan(..)
+                | Instruction::JumpIfLessThan(..)
+                | Instruction::JumpIfFlag(..)
+                | Instruction::SkipIfZero(..)
+                | Instruction::SkipIfNonZero(..) => unreachable!("jumps are lowered to ops"),
             },
         }
 
         self.pc += 1;
 
-        if self.draw {
-            Some((
-                self.float_registers[FloatRegister::X as usize] as isize,
-                self.float_registers[FloatRegister::Y as usize] as isize,
-                0xffffff,
-            ))
+        if self.draw && self.dash(moved) {
+            self.draw_step();
+        } else if moved {
+            self.last_emitted = None;
+            self.emit(self.draw_event(PenState::Up));
+        }
+
+        Ok(&self.events)
+    }
+
+    /// Like [`Vm::step`], but first calls `hook` with the program counter and the instruction
+    /// about to be executed.
+    pub fn step_with_hook(
+        &mut self,
+        program: &CompiledProgram,
+        hook: &mut dyn FnMut(usize, &Instruction),
+    ) -> Result<&[DrawEvent], VmError> {
+        if self.pc < program.len() {
+            hook(self.pc, program.instruction(self.pc));
+        }
+        self.step(program)
+    }
+
+    /// Run `program` without a window until it halts, returning every draw event along the way,
+    /// or an error as soon as it goes over one of the `limits`. The extent of the drawing is then
+    /// available from [`Vm::bounds`].
+    pub fn run(
+        &mut self,
+        program: &CompiledProgram,
+        limits: &Limits,
+    ) -> Result<Vec<DrawEvent>, VmError> {
+        if let Some(limit) = limits.max_memory {
+            if self.memory.len() > limit {
+                return Err(VmError::MemoryLimit {
+                    words: self.memory.len(),
+                    limit,
+                });
+            }
+        }
+
+        // A single ARC, POLY or SPIRAL can draw a lot, so the pixel limit is also checked as
+        // events are recorded
+        self.pixel_limit = limits.max_pixels;
+
+        let mut events = Vec::new();
+        while !self.is_terminated() {
+            let pc = self.pc;
+            if let Some(limit) = limits.max_cycles.filter(|&limit| self.cycles >= limit) {
+                return Err(VmError::CycleLimit { limit, pc });
+            }
+
+            events.extend_from_slice(self.step(program)?);
+
+            if let Some(limit) = limits.max_pixels.filter(|&limit| self.pixels_drawn > limit) {
+                return Err(VmError::PixelLimit { limit, pc });
+            }
+            // PUSH adds one value a step, so the stack can't get far past its limit
+            let depth = self.data_stack.len();
+            if let Some(limit) = limits.max_stack_depth.filter(|&limit| depth > limit) {
+                return Err(VmError::StackLimit { limit, pc });
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// The current angle in radians, read from register `A` in the current angle unit.
+    fn heading(&self) -> f64 {
+        let angle = self.uint_registers[UintRegister::A as usize];
+        match self.angle_unit {
+            AngleUnit::Degrees => ((angle % 360) as f64).to_radians(),
+            AngleUnit::Radians => angle as f64 / 1000.0,
+            AngleUnit::Turns => angle as f64 / 65536.0 * core::f64::consts::TAU,
+        }
+    }
+
+    /// Save the selected turtle's state and switch to `turtle`'s.
+    fn select(&mut self, turtle: usize) {
+        let a = UintRegister::A as usize;
+        let (x, y) = (FloatRegister::X as usize, FloatRegister::Y as usize);
+
+        self.turtles[self.turtle] = Turtle {
+            angle: self.uint_registers[a],
+            x: self.float_registers[x],
+            y: self.float_registers[y],
+            draw: self.draw,
+            color_ramp: self.color_ramp,
+            pen_color: self.pen_color,
+            pen_index: self.pen_index,
+        };
+
+        let next = self.turtles[turtle];
+        self.uint_registers[a] = next.angle;
+        self.float_registers[x] = next.x;
+        self.float_registers[y] = next.y;
+        self.draw = next.draw;
+        self.color_ramp = next.color_ramp;
+        self.pen_color = next.pen_color;
+        self.pen_index = next.pen_index;
+        self.turtle = turtle;
+        self.last_emitted = None;
+    }
+
+    /// Set register `A` to `radians` in the current angle unit.
+    fn set_heading(&mut self, radians: f64) {
+        let radians = if radians < 0.0 {
+            radians + core::f64::consts::TAU
+        } else {
+            radians
+        };
+        let angle = match self.angle_unit {
+            AngleUnit::Degrees => math::round(radians.to_degrees()) as u32 % 360,
+            AngleUnit::Radians => math::round(radians * 1000.0) as u32,
+            AngleUnit::Turns => {
+                math::round(radians / core::f64::consts::TAU * 65536.0) as u32 % 65536
+            }
+        };
+        self.uint_registers[UintRegister::A as usize] = angle as u16;
+    }
+
+    /// Emit points roughly a pixel apart along an arc around the current position.
+    fn arc(&mut self, radius: f64, sweep: f64) {
+        let start = self.heading();
+        let x = self.float_registers[FloatRegister::X as usize];
+        let y = self.float_registers[FloatRegister::Y as usize];
+
+        if !(radius * sweep).is_finite() {
+            return;
+        }
+        let points =
+            (math::ceil(math::abs(radius * sweep)).max(1.0) as usize).min(MAX_SHAPE_POINTS - 1);
+        for i in 0..=points {
+            if self.pixel_limit_reached() {
+                return;
+            }
+            let angle = start + sweep * i as f64 / points as f64;
+            self.shape_point(x + radius * math::cos(angle), y + radius * math::sin(angle));
+        }
+    }
+
+    /// Emit points roughly a pixel apart around a polygon starting at the current position.
+    fn poly(&mut self, sides: u16, length: f64) {
+        let mut angle = self.heading();
+        let mut x = self.float_registers[FloatRegister::X as usize];
+        let mut y = self.float_registers[FloatRegister::Y as usize];
+        if sides > 0 {
+            self.shape_point(x, y);
+        }
+
+        if !length.is_finite() {
+            return;
+        }
+        let per_side = (MAX_SHAPE_POINTS / usize::from(sides.max(1))).max(1);
+        let points = (math::ceil(math::abs(length)).max(1.0) as usize).min(per_side);
+        for _ in 0..sides {
+            let (dx, dy) = (length * math::cos(angle), length * math::sin(angle));
+            for i in 1..=points {
+                if self.pixel_limit_reached() {
+                    return;
+                }
+                let t = i as f64 / points as f64;
+                self.shape_point(x + dx * t, y + dy * t);
+            }
+            x += dx;
+            y += dy;
+            angle += core::f64::consts::TAU / f64::from(sides);
+        }
+    }
+
+    /// Emit points roughly a pixel apart along an Archimedean spiral around the current position.
+    fn spiral(&mut self, turns: u16, growth: f64) {
+        if !growth.is_finite() {
+            return;
+        }
+        let start = self.heading();
+        let x = self.float_registers[FloatRegister::X as usize];
+        let y = self.float_registers[FloatRegister::Y as usize];
+
+        let end = f64::from(turns) * core::f64::consts::TAU;
+        let mut swept = 0.0;
+        // Far enough out a pixel is less than the precision of `swept`, which then stops growing
+        for _ in 0..MAX_SHAPE_POINTS {
+            if self.pixel_limit_reached() {
+                return;
+            }
+            let radius = growth * swept / core::f64::consts::TAU;
+            let angle = start + swept;
+            self.shape_point(x + radius * math::cos(angle), y + radius * math::sin(angle));
+            if swept >= end {
+                break;
+            }
+            // A pixel along the curve, or a radian near the center where that would be tiny
+            swept = (swept + 1.0 / math::abs(radius).max(1.0)).min(end);
+        }
+    }
+
+    fn pixel_limit_reached(&self) -> bool {
+        self.pixel_limit
+            .is_some_and(|limit| self.pixels_drawn > limit)
+    }
+
+    /// Emit a point along a shape drawn by a single instruction, drawn unless the dash pattern is
+    /// off there.
+    fn shape_point(&mut self, x: f64, y: f64) {
+        let kind = if self.dash(true) {
+            PenState::Down
         } else {
-            None
+            PenState::Up
+        };
+        self.emit(DrawEvent {
+            x,
+            y,
+            ..self.draw_event(kind)
+        });
+    }
+
+    /// Draw at the current position, or with pixel steps on, at each pixel since the last one
+    /// drawn.
+    fn draw_step(&mut self) {
+        let event = self.draw_event(PenState::Down);
+        if !self.pixel_steps {
+            self.emit(event);
+            return;
+        }
+
+        let (x, y) = event.pixel();
+        if let Some((last_x, last_y)) = self.last_emitted {
+            if (last_x, last_y) == (x, y) {
+                return;
+            }
+
+            // Moves too long to measure in pixels just draw their end
+            if let (Some(dx), Some(dy)) = (x.checked_sub(last_x), y.checked_sub(last_y)) {
+                // Like shapes, very long moves are drawn with at most MAX_SHAPE_POINTS points
+                let steps = dx.unsigned_abs().max(dy.unsigned_abs());
+                let steps = steps.min(MAX_SHAPE_POINTS);
+                let (dx, dy) = (dx as f64, dy as f64);
+                for i in 1..steps {
+                    if self.pixel_limit_reached() {
+                        break;
+                    }
+                    let t = i as f64 / steps as f64;
+                    self.emit(DrawEvent {
+                        x: last_x as f64 + math::round(dx * t),
+                        y: last_y as f64 + math::round(dy * t),
+                        ..event
+                    });
+                }
+            }
         }
+
+        self.last_emitted = Some((x, y));
+        self.emit(event);
     }
 
-    fn check_conditional<F>(&self, register: Register, value: Value, f: F) -> bool
-    where
-        F: Fn(f64, f64) -> bool,
-    {
+    /// Whether the dash pattern is on a drawn pixel, moving along it a pixel first if `moved`.
+    fn dash(&mut self, moved: bool) -> bool {
+        let [on, off] = match self.dash_pattern {
+            Some(pattern) => pattern,
+            None => return true,
+        };
+        if moved {
+            self.dash_phase = (self.dash_phase + 1) % (u32::from(on) + u32::from(off));
+        }
+        self.dash_phase < u32::from(on)
+    }
+
+    /// Every watchdog period, fail if the registers are the same as at the last check and
+    /// nothing has been drawn in between.
+    fn check_progress(&mut self) -> Result<(), VmError> {
+        let registers = self.register_snapshot();
+        let watchdog = match &mut self.watchdog {
+            Some(watchdog) if self.cycles - watchdog.checked_at >= watchdog.limit => watchdog,
+            _ => return Ok(()),
+        };
+
+        if !watchdog.drew && watchdog.registers == registers {
+            return Err(VmError::NoProgress {
+                cycles: watchdog.limit,
+                pc: self.pc,
+            });
+        }
+        watchdog.checked_at = self.cycles;
+        watchdog.registers = registers;
+        watchdog.drew = false;
+        Ok(())
+    }
+
+    fn register_snapshot(&self) -> ([u16; 8], [u64; 8]) {
+        let mut floats = [0; 8];
+        for (bits, float) in floats.iter_mut().zip(&self.float_registers) {
+            *bits = float.to_bits();
+        }
+        (self.uint_registers, floats)
+    }
+
+    /// Emit `event`, along with its rotated and reflected copies when `MIRROR` is on.
+    fn emit(&mut self, event: DrawEvent) {
+        let (ways, reflect) = match self.symmetry {
+            Some(symmetry) => symmetry,
+            None => return self.record(event),
+        };
+
+        for i in 0..ways {
+            let angle = core::f64::consts::TAU * f64::from(i) / f64::from(ways);
+            let (sin, cos) = (math::sin(angle), math::cos(angle));
+            let copy = DrawEvent {
+                x: event.x * cos - event.y * sin,
+                y: event.x * sin + event.y * cos,
+                ..event
+            };
+            // The first copy is the event itself, left unrotated so it lands exactly
+            self.record(if i == 0 { event } else { copy });
+            if reflect {
+                self.record(DrawEvent { y: -copy.y, ..copy });
+            }
+        }
+    }
+
+    fn record(&mut self, event: DrawEvent) {
+        if self.pixel_limit_reached() {
+            return;
+        }
+
+        if self.dedup && event.wait == 0 {
+            if let Some(last) = self.last_recorded {
+                // The same apart from where in the pixel it is
+                let in_place = DrawEvent {
+                    x: last.x,
+                    y: last.y,
+                    ..event
+                };
+                if last.pixel() == event.pixel() && in_place == last {
+                    return;
+                }
+            }
+            self.last_recorded = Some(event);
+        }
+
+        if event.kind != PenState::Up {
+            self.pixels_drawn += 1;
+        }
+        if event.kind == PenState::Down {
+            if let Some(watchdog) = &mut self.watchdog {
+                watchdog.drew = true;
+            }
+
+            match &mut self.bounds {
+                Some(bounds) => bounds.include(event.x, event.y),
+                None => {
+                    self.bounds = Some(Bounds {
+                        min_x: event.x,
+                        min_y: event.y,
+                        max_x: event.x,
+                        max_y: event.y,
+                    })
+                }
+            }
+        }
+
+        if let Some(canvas) = &mut self.canvas {
+            canvas.draw_event(&event);
+        }
+        self.events.push(event);
+    }
+
+    fn draw_event(&self, kind: PenState) -> DrawEvent {
+        DrawEvent {
+            x: self.float_registers[FloatRegister::X as usize],
+            y: self.float_registers[FloatRegister::Y as usize],
+            color: self.color(),
+            kind,
+            width: self.pen_width,
+            palette_index: self.palette_index(),
+            wait: 0,
+        }
+    }
+
+    /// The palette index of the pen color, unless it comes from somewhere other than `COLI`.
+    fn palette_index(&self) -> Option<u8> {
+        match self.color_ramp {
+            Some(_) => None,
+            None => self.pen_index,
+        }
+    }
+
+    fn color(&self) -> u32 {
+        match self.color_ramp {
+            Some(period) => {
+                let period = period as usize;
+                let hue = (self.cycles % period) as f64 / period as f64 * 360.0;
+                hsv_to_rgb(hue, 1.0, 1.0)
+            }
+            None => self.pen_color.unwrap_or(0xffffff),
+        }
+    }
+
+    /// Send `message` to the logger, or STDERR without one.
+    fn log(&mut self, level: Level, message: &str) {
+        match &mut self.logger {
+            Some(logger) => logger.log(level, message),
+            #[cfg(feature = "std")]
+            None => crate::log::Stderr::default().log(level, message),
+            #[cfg(not(feature = "std"))]
+            None => {}
+        }
+    }
+
+    /// Warn that `register` overflowed, unless the VM is quiet.
+    fn warn_overflow(&mut self, register: impl fmt::Debug) {
+        if !self.quiet {
+            self.log(Level::Warning, &format!("{:?} overflowed", register));
+        }
+    }
+
+    fn print_registers(&mut self) {
+        if self.debug_enabled {
+            let registers = |range: core::ops::Range<u8>| -> Vec<String> {
+                range
+                    .filter_map(Register::from_u8)
+                    .map(|register| {
+                        format!(
+                            "{}={}",
+                            register,
+                            self.unwrap_float_value(Value::Register(register))
+                        )
+                    })
+                    .collect()
+            };
+
+            let message = format!(
+                "pc {} cycle {}\n  {}\n  {}",
+                self.pc,
+                self.cycles,
+                registers(0x0..0x8).join(" "),
+                registers(0x8..0x10).join(" ")
+            );
+            self.log(Level::Debug, &message);
+        }
+    }
+
+    fn set_register(&mut self, register: Register, value: Value) {
         match register {
             Register::UintRegister(r) => {
-                let value = self.unwrap_uint_value(value);
-                // NOTE: Not ideal to be converting u16 to f64 before we run the predicate function
-                // but otherwise we can't re-use the same function.
-                f(self.uint_registers[r as usize] as f64, value as f64)
+                self.uint_registers[r as usize] = self.unwrap_uint_value(value);
             }
             Register::FloatRegister(r) => {
-                let value = self.unwrap_float_value(value);
-                f(self.float_registers[r as usize], value)
+                self.float_registers[r as usize] = self.unwrap_float_value(value);
             }
         }
     }
 
+    /// Check the registers `instruction` reads have been written, then mark the one it writes.
+    fn check_initialized(&mut self, instruction: &Instruction) -> Result<(), VmError> {
+        if let Some(&register) = instruction
+            .reads()
+            .iter()
+            .find(|&&register| !self.is_written(register))
+        {
+            return Err(VmError::UninitializedRead {
+                register,
+                pc: self.pc,
+            });
+        }
+        if let Some(register) = instruction.writes() {
+            self.written |= lint::bit(register);
+        }
+        Ok(())
+    }
+
+    fn is_written(&self, register: Register) -> bool {
+        (self.written | lint::INITIALIZED) & lint::bit(register) != 0
+    }
+
+    /// The register numbered by the value in `index`.
+    fn indirect_register(&self, index: Register) -> Result<Register, VmError> {
+        let index = self.unwrap_uint_value(Value::Register(index));
+        u8::try_from(index)
+            .ok()
+            .and_then(Register::from_u8)
+            .ok_or(VmError::InvalidRegister { index, pc: self.pc })
+    }
+
+    /// The index into memory of the address in `addr`.
+    fn memory_address(&self, addr: Register) -> Result<usize, VmError> {
+        let addr = self.unwrap_uint_value(Value::Register(addr));
+        if (addr as usize) < self.memory.len() {
+            Ok(addr as usize)
+        } else {
+            Err(VmError::MemoryOutOfBounds { addr, pc: self.pc })
+        }
+    }
+
+    /// `value` as a uint, with floats converted as documented on [`Instruction::Store`].
     fn unwrap_uint_value(&self, value: Value) -> u16 {
+        // Float to int casts saturate, and are 0 for NaN
         match value {
             Value::Uint(v) => v,
             Value::Float(v) => v as u16,
@@ -211,7 +1379,58 @@ impl Vm {
         }
     }
 
+    /// Execution counts indexed by instruction, if the VM was built with [`Vm::with_profiling`].
+    /// There's a count for each instruction of the longest program stepped.
+    pub fn profile_report(&self) -> Option<&[u64]> {
+        self.profile.as_deref()
+    }
+
+    /// The bounding box of every pixel drawn so far, if anything has been drawn.
+    pub fn bounds(&self) -> Option<Bounds> {
+        self.bounds
+    }
+
+    /// How many instructions have been executed.
+    pub fn cycles(&self) -> usize {
+        self.cycles
+    }
+
+    /// A summary of the run so far.
+    pub fn stats(&self) -> RunStats {
+        RunStats {
+            cycles: self.cycles,
+            pixels_drawn: self.pixels_drawn,
+            bounds: self.bounds,
+            halted: self.terminated,
+        }
+    }
+
     pub fn is_terminated(&self) -> bool {
         self.terminated
     }
 }
+
+/// Pack a `0xRRGGBB` color into 16 bits, keeping the high five bits of red and blue and six of
+/// green.
+fn rgb565(color: u32) -> u16 {
+    ((color >> 8 & 0xf800) | (color >> 5 & 0x07e0) | (color >> 3 & 0x001f)) as u16
+}
+
+/// Convert a hue in degrees, and saturation and value in `0.0..=1.0`, to a `0x00RRGGBB` color.
+fn hsv_to_rgb(h: f64, s: f64, v: f64) -> u32 {
+    let c = v * s;
+    let h = (h % 360.0) / 60.0;
+    let x = c * (1.0 - math::abs(h % 2.0 - 1.0));
+    let (r, g, b) = match h as u8 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = v - c;
+    let channel = |n: f64| math::round((n + m) * 255.0) as u32;
+
+    channel(r) << 16 | channel(g) << 8 | channel(b)
+}
@@ -0,0 +1,56 @@
+//! Bindings for running programs in the browser, built with the `wasm` feature. The library is
+//! only an rlib, since a no_std cdylib can't link, so build the module for wasm-bindgen with:
+//!
+//! ```text
+//! cargo rustc --lib --release --target wasm32-unknown-unknown --no-default-features \
+//!     --features wasm --crate-type cdylib
+//! ```
+
+use wasm_bindgen::prelude::*;
+
+use crate::buffer::Buffer;
+use crate::compile::CompiledProgram;
+use crate::instruction::{decode, Header, Instruction};
+use crate::vm::{Limits, Vm, VmError};
+
+/// Upper bound on instructions executed so a program that never halts errors rather than
+/// hanging the page.
+const MAX_STEPS: usize = 10_000_000;
+
+/// Run an assembled program and return its drawing as RGBA bytes, row by row. The image is the
+/// size given in the program's header, see [`program_size`].
+#[wasm_bindgen]
+pub fn run_program(bytes: &[u8]) -> Result<Vec<u8>, JsValue> {
+    let (header, program) = decode(bytes).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let buffer = render(&header, &program).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    Ok(buffer.to_rgba8())
+}
+
+/// The width and height of the image `run_program` returns for an assembled program.
+#[wasm_bindgen]
+pub fn program_size(bytes: &[u8]) -> Result<Vec<u16>, JsValue> {
+    let (header, _) = decode(bytes).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    Ok(vec![header.width, header.height])
+}
+
+/// Run `program` headlessly, drawing into a buffer the way the window does with 0,0 in the
+/// center.
+fn render(header: &Header, program: &[Instruction]) -> Result<Buffer, VmError> {
+    let mut vm = Vm::default()
+        .with_memory(header.memory)
+        .with_palette(header.palette.clone());
+    if program.iter().any(|i| matches!(i, Instruction::Sample(_))) {
+        vm = vm.with_canvas(header.width, header.height, header.background);
+    }
+    let events = vm.run(&CompiledProgram::new(program), &Limits::cycles(MAX_STEPS))?;
+
+    let mut buffer = Buffer::new_with_background(
+        header.width as usize,
+        header.height as usize,
+        header.background,
+    );
+    for event in &events {
+        buffer.draw_event(event);
+    }
+    Ok(buffer)
+}
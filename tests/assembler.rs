@@ -0,0 +1,178 @@
+mod common;
+
+use common::{assemble, assemble_with};
+use drawer::instruction::{decode, Instruction};
+
+/// Assemble `source` as a 16x16 program and decode its instructions.
+fn instructions(name: &str, source: &str) -> Vec<Instruction> {
+    let source = format!("WIDTH 16\nHEIGHT 16\n{}", source);
+    decode(&assemble(name, &source)).unwrap().1
+}
+
+/// Assemble `source` as a 16x16 program, returning the assembler's errors if it fails.
+fn errors(name: &str, source: &str) -> Result<Vec<u8>, String> {
+    assemble_with(name, &format!("WIDTH 16\nHEIGHT 16\n{}", source), &[], &[])
+}
+
+#[test]
+fn hash_comments() {
+    assert_eq!(
+        instructions("hash-comments", "# a comment\nSTO A 1 # set A\nHLT#done\n"),
+        instructions("hash-comments-plain", "STO A 1\nHLT\n")
+    );
+}
+
+#[test]
+fn unexpected_operand() {
+    let e = errors("unexpected-operand", "STO A 1 2\nHLT\n").unwrap_err();
+    assert!(e.contains("unexpected operand: 2"), "{}", e);
+}
+
+#[test]
+fn several_statements() {
+    assert_eq!(
+        instructions("several", "STO B 2; loop:; DEC B; JNZ B loop:;; HLT\n"),
+        instructions("several-lines", "STO B 2\nloop:\nDEC B\nJNZ B loop:\nHLT\n")
+    );
+}
+
+#[test]
+fn semicolon_comments() {
+    assert_eq!(
+        instructions(
+            "semicolon-comments",
+            "; CMP Rx n\nSTO A 1 ; set A\nINC A; FWD ; then move\nHLT\n"
+        ),
+        instructions("semicolon-comments-plain", "STO A 1\nINC A\nFWD\nHLT\n")
+    );
+}
+
+#[test]
+fn repeats() {
+    assert_eq!(
+        instructions("repeats", "REP 2\nINC A\nREP 3\nFWD\nENDR\nENDR\nHLT\n"),
+        instructions(
+            "repeats-plain",
+            "INC A\nFWD\nFWD\nFWD\nINC A\nFWD\nFWD\nFWD\nHLT\n"
+        )
+    );
+    assert_eq!(
+        instructions("repeat-none", "REP 0\nINC A\nENDR\nHLT\n"),
+        instructions("repeat-none-plain", "HLT\n")
+    );
+}
+
+#[test]
+fn labels_after_repeats() {
+    // `end:` is after every repeated instruction, not just one copy of the block
+    assert_eq!(
+        instructions(
+            "labels-after-repeats",
+            "REP 3\nFWD\nENDR\nJNZ B end:\nend:\nHLT\n"
+        ),
+        instructions(
+            "labels-after-repeats-plain",
+            "FWD\nFWD\nFWD\nJNZ B end:\nend:\nHLT\n"
+        )
+    );
+}
+
+#[test]
+fn local_labels() {
+    assert_eq!(
+        instructions(
+            "local-labels",
+            "a:\nSTO B 2\n.loop:\nDEC B\nJNZ B .loop:\nb:\nSTO B 2\n.loop:\nDEC B\nJNZ B .loop:\nHLT\n"
+        ),
+        instructions(
+            "local-labels-plain",
+            "a:\nSTO B 2\na_loop:\nDEC B\nJNZ B a_loop:\nb:\nSTO B 2\nb_loop:\nDEC B\nJNZ B b_loop:\nHLT\n"
+        )
+    );
+}
+
+#[test]
+fn expressions() {
+    assert_eq!(
+        instructions(
+            "expressions",
+            "CONST SIZE 4\nCONST AREA SIZE*SIZE-1\nSTO A AREA+2*3\nSTO B SIZE-SIZE\nHLT\n"
+        ),
+        instructions("expressions-plain", "STO A 21\nSTO B 0\nHLT\n")
+    );
+
+    let e = errors("expressions-negative", "STO A 1-2\nHLT\n").unwrap_err();
+    assert!(e.contains("out of range for a u16: 1-2"), "{}", e);
+}
+
+#[test]
+fn commas() {
+    assert_eq!(
+        instructions("commas", "STO A, 1\nADD A,B\nHLT\n"),
+        instructions("commas-plain", "STO A 1\nADD A B\nHLT\n")
+    );
+}
+
+#[test]
+fn include_cycles() {
+    // Each file is only included once, so a cycle stops instead of recursing forever
+    let files = [
+        ("a.asm", "INC A\nINCLUDE b.asm\n"),
+        ("b.asm", "INC B\nINCLUDE a.asm\n"),
+    ];
+    let source = "WIDTH 16\nHEIGHT 16\nINCLUDE a.asm\nHLT\n";
+    let program = assemble_with("include-cycles", source, &[], &files).unwrap();
+    assert_eq!(
+        decode(&program).unwrap().1,
+        instructions("include-cycles-plain", "INC A\nINC B\nHLT\n")
+    );
+
+    let e = errors("include-missing", "INCLUDE missing.asm\nHLT\n").unwrap_err();
+    assert!(e.contains("unable to read"), "{}", e);
+}
+
+#[test]
+fn align() {
+    let source = "WIDTH 16\nHEIGHT 16\nINC A\nHLT\n";
+    for align in &["1", "7", "64"] {
+        let program = assemble_with("align", source, &["--align", align], &[]).unwrap();
+        assert!(program.len().is_multiple_of(align.parse().unwrap()));
+
+        // Only HLTs are added
+        let mut expected = instructions("align-plain", "INC A\nHLT\n");
+        let program = decode(&program).unwrap().1;
+        expected.resize(program.len(), Instruction::Halt);
+        assert_eq!(program, expected);
+    }
+
+    let e = assemble_with("align-zero", source, &["--align", "0"], &[]).unwrap_err();
+    assert!(e.contains("invalid alignment: 0"), "{}", e);
+}
+
+#[test]
+fn aliases() {
+    assert_eq!(
+        instructions(
+            "aliases",
+            "ALIAS count B\nSTO count 2\nloop:\nDEC count\nJNZ count loop:\nHLT\n"
+        ),
+        instructions("aliases-plain", "STO B 2\nloop:\nDEC B\nJNZ B loop:\nHLT\n")
+    );
+
+    let e = errors("alias-register", "ALIAS A B\nHLT\n").unwrap_err();
+    assert!(e.contains("invalid alias name: A"), "{}", e);
+}
+
+#[test]
+fn embedded_includes() {
+    // The embedded source has the included lines, so it assembles without the included file
+    let files = [("lib.asm", "INC A\n")];
+    let source = "WIDTH 16\nHEIGHT 16\nINCLUDE lib.asm\nHLT\n";
+    let program = assemble_with("embedded-includes", source, &["--embed-source"], &files).unwrap();
+    let (header, instructions) = decode(&program).unwrap();
+
+    let embedded = header.source.unwrap();
+    assert_eq!(embedded, "WIDTH 16\nHEIGHT 16\nINC A\nHLT\n");
+    let rebuilt = assemble_with("embedded-includes-rebuilt", &embedded, &[], &[]).unwrap();
+    assert_eq!(decode(&rebuilt).unwrap().1, instructions);
+}
@@ -0,0 +1,41 @@
+mod common;
+
+use common::assemble;
+use drawer::builder::ProgramBuilder;
+use drawer::instruction::{Header, UintRegister::B};
+
+#[test]
+fn same_as_assembler() {
+    let program = ProgramBuilder::new(Header::new(64, 64))
+        .draw()
+        .sto(B, 10)
+        .label("loop")
+        .fwd()
+        .dec(B)
+        .jnz(B, "loop")
+        .jeq(B, 0, "end")
+        .fwd()
+        .label("end")
+        .hlt()
+        .build()
+        .unwrap();
+
+    let source = "WIDTH 64\nHEIGHT 64\nDRW\nSTO B 10\nloop:\nFWD\nDEC B\nJNZ B loop:\n\
+                  JEQ B 0 end:\nFWD\nend:\nHLT\n";
+    assert_eq!(program, assemble("builder", source));
+}
+
+#[test]
+fn wide_addresses() {
+    // A label past the first 65536 instructions needs four byte addresses
+    let mut builder = ProgramBuilder::new(Header::new(64, 64));
+    builder.sto(B, 1).jnz(B, "end");
+    for _ in 0..70_000 {
+        builder.fwd();
+    }
+    let program = builder.label("end").hlt().build().unwrap();
+
+    let source = "WIDTH 64\nHEIGHT 64\nSTO B 1\nJNZ B end:\nREP 2\nREP 35000\nFWD\nENDR\nENDR\n\
+                  end:\nHLT\n";
+    assert_eq!(program, assemble("builder-wide", source));
+}
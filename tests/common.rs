@@ -0,0 +1,96 @@
+// Each test file only uses some of these helpers
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use drawer::buffer::Buffer;
+use drawer::compile::CompiledProgram;
+use drawer::instruction::decode;
+use drawer::vm::{Limits, Vm};
+
+/// Upper bound on instructions executed per program so a broken program fails instead of hanging.
+pub const MAX_STEPS: usize = 50_000_000;
+
+/// Assemble `source` with the `assembler` binary and return the bytes it wrote.
+pub fn assemble(name: &str, source: &str) -> Vec<u8> {
+    assemble_with(name, source, &[], &[])
+        .unwrap_or_else(|e| panic!("{} failed to assemble: {}", name, e))
+}
+
+/// Assemble `source` with the assembler's `args`, next to `files` given as paths and contents
+/// for `INCLUDE`. Returns the bytes written, or what the assembler printed if it failed.
+pub fn assemble_with(
+    name: &str,
+    source: &str,
+    args: &[&str],
+    files: &[(&str, &str)],
+) -> Result<Vec<u8>, String> {
+    let dir = std::env::temp_dir().join(format!("drawer-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for (path, contents) in files {
+        fs::write(dir.join(path), contents).unwrap();
+    }
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_assembler"))
+        .args(args)
+        .current_dir(&dir)
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("unable to run assembler");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(source.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    let program = if output.status.success() {
+        Ok(fs::read(dir.join("program.bin")).unwrap())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).into_owned())
+    };
+    fs::remove_dir_all(&dir).unwrap();
+    program
+}
+
+/// Decode and run `program` headlessly, drawing into a buffer the way the window does.
+pub fn render(program: &[u8]) -> Buffer {
+    let (header, program) = decode(program).unwrap();
+    let (width, height) = (header.width as usize, header.height as usize);
+
+    let mut vm = Vm::default()
+        .with_memory(header.memory)
+        .with_palette(header.palette)
+        .with_canvas(header.width, header.height, header.background);
+    let events = vm
+        .run(&CompiledProgram::new(&program), &Limits::cycles(MAX_STEPS))
+        .unwrap();
+    assert!(vm.is_terminated(), "program did not halt");
+
+    let mut buffer = Buffer::new_with_background(width, height, header.background);
+    for event in &events {
+        buffer.draw_event(event);
+    }
+    buffer
+}
+
+/// FNV-1a over the buffer's pixels, stable across platforms and Rust versions.
+pub fn hash(buffer: &Buffer) -> u64 {
+    buffer
+        .buffer()
+        .iter()
+        .flat_map(|pixel| pixel.to_le_bytes())
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+}
+
+/// Assemble, run and hash the example program `examples/<name>.asm`.
+pub fn example_hash(name: &str) -> u64 {
+    let source = fs::read_to_string(format!("examples/{}.asm", name)).unwrap();
+    hash(&render(&assemble(name, &source)))
+}
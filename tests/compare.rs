@@ -0,0 +1,46 @@
+use drawer::compile::CompiledProgram;
+use drawer::instruction::{Address, FloatRegister, Instruction, Value};
+use drawer::vm::{Limits, Vm};
+
+/// Whether `JEQ` finds `a` and `b` equal, by where the program draws.
+fn jeq(a: f64, b: f64) -> bool {
+    let program = [
+        Instruction::Store(FloatRegister::S.into(), Value::Float(a)),
+        Instruction::JumpIfEqual(FloatRegister::S.into(), Value::Float(b), Address::from(4)),
+        Instruction::Draw,
+        Instruction::Halt,
+        Instruction::Store(FloatRegister::X.into(), 1.into()),
+        Instruction::Draw,
+        Instruction::Halt,
+    ];
+    let events = Vm::default()
+        .run(&CompiledProgram::new(&program), &Limits::default())
+        .unwrap();
+    events.last().unwrap().x == 1.0
+}
+
+#[test]
+fn equal_despite_rounding_at_large_magnitude() {
+    // 300000.00000000006 and 300000.0, a unit in the last place apart
+    assert!(jeq((0.1 + 0.2) * 1e6, 0.3 * 1e6));
+    assert!(jeq((0..10).map(|_| 0.1).sum::<f64>() * 1e8, 1e8));
+}
+
+#[test]
+fn different_at_large_magnitude() {
+    assert!(!jeq(1e6, 1e6 + 0.001));
+    assert!(!jeq(1e15, 1e15 + 1.0));
+}
+
+#[test]
+fn tiny_values_equal_zero() {
+    assert!(jeq(1e-17, 0.0));
+    assert!(!jeq(1e-3, 0.0));
+}
+
+#[test]
+fn infinities() {
+    assert!(jeq(f64::INFINITY, f64::INFINITY));
+    assert!(!jeq(f64::INFINITY, f64::MAX));
+    assert!(!jeq(f64::NAN, f64::NAN));
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use common::{assemble, hash, render};
+use drawer::instruction::{concat, decode, encode};
+
+/// A 64x64 canvas.
+const HEADER: &str = "WIDTH 64\nHEIGHT 64\n";
+
+/// Draw a line `length` long with a loop whose label is `label`.
+fn line(label: &str, length: u16) -> String {
+    format!(
+        "STO E {}\n{}:\nFWD\nDEC E\nJNZ E {}:\n",
+        length, label, label
+    )
+}
+
+#[test]
+fn relocates_jumps() {
+    let a = assemble("concat-a", &format!("{}DRW\n{}", HEADER, line("a", 10)));
+    let b = assemble(
+        "concat-b",
+        &format!("{}STO A 90\n{}HLT\n", HEADER, line("b", 20)),
+    );
+    let both = assemble(
+        "concat-both",
+        &format!(
+            "{}DRW\n{}STO A 90\n{}HLT\n",
+            HEADER,
+            line("a", 10),
+            line("b", 20)
+        ),
+    );
+
+    let (header, a) = decode(&a).unwrap();
+    let (_, b) = decode(&b).unwrap();
+    let program = concat(&a, &b).unwrap();
+
+    assert_eq!(program, decode(&both).unwrap().1);
+    assert_eq!(
+        hash(&render(&encode(&header, &program))),
+        hash(&render(&both))
+    );
+}
+
+#[test]
+fn target_past_two_byte_addresses() {
+    let (mut header, a) = decode(&assemble("concat-long", &format!("{}HLT\n", HEADER))).unwrap();
+    let a = a.repeat(usize::from(u16::MAX));
+    let b = decode(&assemble(
+        "concat-jump",
+        &format!("{}{}HLT\n", HEADER, line("b", 1)),
+    ))
+    .unwrap()
+    .1;
+
+    let program = concat(&a, &b).unwrap();
+    assert_eq!(program[a.len() + 3].jump_target(), Some(a.len() + 1));
+
+    header.wide_addresses = true;
+    assert_eq!(
+        decode(&encode(&header, &program)).unwrap(),
+        (header, program)
+    );
+}
+
+#[test]
+fn register_jumps_are_not_moved() {
+    let (_, a) = decode(&assemble("concat-plain", &format!("{}HLT\n", HEADER))).unwrap();
+    let (_, b) = decode(&assemble(
+        "concat-jmpr",
+        &format!("{}LEA end: B\nJMPR B\nend:\nHLT\n", HEADER),
+    ))
+    .unwrap();
+
+    assert_eq!(concat(&a, &b), None);
+    assert!(concat(&b, &a).is_some());
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use std::fs;
+
+use common::assemble;
+use drawer::instruction::{decode, encode, DecodeError, Header, Instruction};
+
+/// A version 5 header for a 16x16 canvas with no memory, background or palette.
+const HEADER: [u8; 13] = [
+    0x05, 0x00, 0x10, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// xorshift64, so the bytes are the same every run without pulling in a dependency.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+}
+
+#[test]
+fn random_bytes() {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for _ in 0..10_000 {
+        let len = (rng.next() % 64) as usize;
+        let _ = decode(&rng.bytes(len));
+    }
+}
+
+#[test]
+fn random_instructions() {
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    for _ in 0..10_000 {
+        let len = (rng.next() % 64) as usize;
+        let mut program = HEADER.to_vec();
+        program.extend(rng.bytes(len));
+        let _ = decode(&program);
+    }
+}
+
+#[test]
+fn invalid_register() {
+    for register in 16..=u8::MAX {
+        let mut program = HEADER.to_vec();
+        // INC
+        program.extend([0x04, register]);
+        assert_eq!(
+            decode(&program),
+            Err(DecodeError::InvalidRegister {
+                register,
+                offset: HEADER.len() + 1,
+            })
+        );
+    }
+}
+
+#[test]
+fn truncated_program() {
+    let source = fs::read_to_string("examples/all.asm").unwrap();
+    let program = assemble("truncated", &source);
+
+    assert!(decode(&program).is_ok());
+    for len in 0..program.len() {
+        let _ = decode(&program[..len]);
+    }
+}
+
+#[test]
+fn embedded_source() {
+    let program = [Instruction::Forward, Instruction::Halt];
+    let mut header = Header::new(16, 16);
+    // Bytes that would decode as a FWD and an INC with a bad register if read as instructions
+    header.source = Some("\u{2}\u{4}\u{ff}".to_string());
+
+    assert_eq!(
+        decode(&encode(&header, &program)),
+        Ok((header, program.to_vec()))
+    );
+}
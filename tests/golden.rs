@@ -0,0 +1,23 @@
+mod common;
+
+use common::example_hash;
+
+#[test]
+fn spiral() {
+    assert_eq!(example_hash("spiral"), 0xa8aa3e28fcba71fc);
+}
+
+#[test]
+fn star() {
+    assert_eq!(example_hash("star"), 0xa4e9b822454d1565);
+}
+
+#[test]
+fn rainbow() {
+    assert_eq!(example_hash("rainbow"), 0x096885f14b92d53a);
+}
+
+#[test]
+fn all() {
+    assert_eq!(example_hash("all"), 0xca4719bbad057648);
+}
@@ -0,0 +1,44 @@
+mod common;
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use common::assemble_with;
+
+/// Run the `hexdump` binary on `program` and return its lines.
+fn hexdump(program: &[u8]) -> Vec<String> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_hexdump"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("unable to run hexdump");
+    child.stdin.take().unwrap().write_all(program).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn embedded_source() {
+    let source = "WIDTH 16\nHEIGHT 16\nFWD\nHLT\n";
+    let program = assemble_with("hexdump-source", source, &["--embed-source"], &[]).unwrap();
+    let lines = hexdump(&program);
+
+    let instructions: Vec<&String> = lines.iter().filter(|line| line.contains(": ")).collect();
+    assert_eq!(instructions.len(), 2);
+    assert!(instructions[1].ends_with("08                       1: HLT"));
+
+    let source_at = lines
+        .iter()
+        .position(|line| line.ends_with("source"))
+        .unwrap();
+    assert!(source_at > lines.iter().position(|line| line.contains("HLT")).unwrap());
+    assert!(lines
+        .last()
+        .unwrap()
+        .ends_with(&format!("source length {}", source.len())));
+}
@@ -0,0 +1,76 @@
+use drawer::compile::CompiledProgram;
+use drawer::instruction::{Instruction::*, UintRegister::B};
+use drawer::lint::{lint, Lint};
+use drawer::vm::{Limits, Vm, VmError};
+
+#[test]
+fn clean_program() {
+    let program = [
+        Store(B.into(), 2.into()),
+        Draw,
+        Forward,
+        Decrement(B.into()),
+        JumpIfNonZero(B.into(), 2.into()),
+        Halt,
+    ];
+    assert_eq!(lint(&program), vec![]);
+}
+
+#[test]
+fn uninitialized_read() {
+    let program = [Increment(B.into()), Halt];
+    assert_eq!(
+        lint(&program),
+        vec![Lint::UninitializedRead {
+            register: B.into(),
+            pc: 0
+        }]
+    );
+}
+
+#[test]
+fn ambiguous_pen() {
+    // The jump skips the DRW, so the FWD may or may not draw
+    let program = [
+        Store(B.into(), 1.into()),
+        JumpIfNonZero(B.into(), 3.into()),
+        Draw,
+        Forward,
+        Halt,
+    ];
+    assert_eq!(lint(&program), vec![Lint::AmbiguousPen { pc: 3 }]);
+}
+
+#[test]
+fn jump_out_of_range() {
+    let program = [
+        Store(B.into(), 1.into()),
+        JumpIfNonZero(B.into(), 5.into()),
+        Halt,
+    ];
+    assert_eq!(
+        lint(&program),
+        vec![Lint::JumpOutOfRange { target: 5, pc: 1 }]
+    );
+}
+
+#[test]
+fn runs_off_end() {
+    let program = [Draw, Forward];
+    assert_eq!(lint(&program), vec![Lint::RunsOffEnd { pc: 1 }]);
+}
+
+#[test]
+fn strict() {
+    let program = CompiledProgram::new(&[Increment(B.into()), Halt]);
+    let limits = Limits::cycles(10);
+
+    assert!(Vm::default().run(&program, &limits).is_ok());
+    assert_eq!(
+        Vm::default().strict().run(&program, &limits),
+        Err(VmError::UninitializedRead {
+            register: B.into(),
+            pc: 0
+        })
+    );
+}
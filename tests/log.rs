@@ -0,0 +1,41 @@
+use std::sync::{Arc, Mutex};
+
+use drawer::compile::CompiledProgram;
+use drawer::instruction::{Instruction, UintRegister, Value};
+use drawer::log::Level;
+use drawer::vm::{Limits, Vm};
+
+/// Run `program` and return everything it logged.
+fn logged(vm: Vm, program: &[Instruction]) -> Vec<(Level, String)> {
+    let logged = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&logged);
+    let mut vm = vm.with_logger(move |level, message: &str| {
+        sink.lock().unwrap().push((level, message.to_string()))
+    });
+    vm.run(&CompiledProgram::new(program), &Limits::default())
+        .unwrap();
+    let logged = logged.lock().unwrap().clone();
+    logged
+}
+
+/// Add past the largest uint.
+fn overflow() -> [Instruction; 3] {
+    [
+        Instruction::Store(UintRegister::B.into(), Value::Uint(u16::MAX)),
+        Instruction::Add(UintRegister::B.into(), Value::Uint(2)),
+        Instruction::Halt,
+    ]
+}
+
+#[test]
+fn overflow_warns() {
+    assert_eq!(
+        logged(Vm::default(), &overflow()),
+        vec![(Level::Warning, "B overflowed".to_string())]
+    );
+}
+
+#[test]
+fn quiet_drops_overflow_warnings() {
+    assert_eq!(logged(Vm::default().quiet(), &overflow()), vec![]);
+}
@@ -0,0 +1,18 @@
+use std::convert::TryFrom;
+
+use drawer::Opcode;
+
+#[test]
+fn fwd_is_0x02() {
+    assert_eq!(Opcode::try_from("FWD").unwrap() as u8, 0x02);
+    assert_eq!(Opcode::try_from(0x02), Ok(Opcode::FWD));
+}
+
+#[test]
+fn names_and_bytes_agree() {
+    for &opcode in Opcode::all() {
+        let name = format!("{:?}", opcode);
+        assert_eq!(Opcode::try_from(name.as_str()), Ok(opcode));
+        assert_eq!(Opcode::try_from(opcode as u8), Ok(opcode));
+    }
+}
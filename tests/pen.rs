@@ -0,0 +1,36 @@
+use drawer::buffer::Buffer;
+use drawer::compile::CompiledProgram;
+use drawer::instruction::{Instruction, Value, MAX_DIMENSION};
+use drawer::vm::{DrawEvent, Limits, PenState, Vm};
+
+#[test]
+fn far_wide_dots_are_clipped() {
+    let mut buffer = Buffer::new(4, 4);
+    for &(x, y) in &[(1e300, 0.0), (0.0, -1e300), (f64::MAX, f64::MIN)] {
+        buffer.draw_event(&DrawEvent {
+            x,
+            y,
+            color: 0xffffff,
+            kind: PenState::Down,
+            width: 3,
+            palette_index: None,
+            wait: 0,
+        });
+    }
+    assert!(buffer.buffer().iter().all(|&pixel| pixel == 0));
+}
+
+#[test]
+fn pen_width_is_capped() {
+    let program = [
+        Instruction::PenWidth(Value::Uint(u16::MAX)),
+        Instruction::Draw,
+        Instruction::Forward,
+        Instruction::Halt,
+    ];
+    let events = Vm::default()
+        .run(&CompiledProgram::new(&program), &Limits::cycles(10))
+        .unwrap();
+    assert!(!events.is_empty());
+    assert!(events.iter().all(|event| event.width == MAX_DIMENSION));
+}
@@ -0,0 +1,43 @@
+use drawer::instruction::Register;
+use drawer::register_from_str;
+
+const NAMES: [&str; 16] = [
+    "A", "B", "C", "D", "E", "F", "G", "H", "S", "T", "U", "V", "W", "X", "Y", "Z",
+];
+
+#[test]
+fn names_round_trip() {
+    for name in NAMES.iter() {
+        let byte = register_from_str(name).unwrap();
+        let register = Register::from_u8(byte).unwrap();
+        assert_eq!(register.to_string(), *name);
+    }
+}
+
+#[test]
+fn bytes_round_trip() {
+    for byte in 0..=u8::MAX {
+        if let Some(register) = Register::from_u8(byte) {
+            assert_eq!(register_from_str(&register.to_string()), Ok(byte));
+        }
+    }
+}
+
+#[test]
+fn unknown_register() {
+    assert!(register_from_str("I").is_err());
+    assert!(register_from_str("a").is_err());
+    assert!(register_from_str("").is_err());
+}
+
+#[test]
+fn only_sixteen_bytes_are_registers() {
+    for byte in 0..=u8::MAX {
+        assert_eq!(
+            Register::from_u8(byte).is_some(),
+            byte < 16,
+            "byte {}",
+            byte
+        );
+    }
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use std::fs;
+
+use common::{assemble, MAX_STEPS};
+use drawer::compile::CompiledProgram;
+use drawer::instruction::decode;
+use drawer::vm::{Limits, Vm};
+
+/// Assemble `examples/all.asm`, which uses every instruction, and a VM set up to run it.
+fn all() -> (CompiledProgram, Vm) {
+    let source = fs::read_to_string("examples/all.asm").unwrap();
+    let (header, program) = decode(&assemble("reset", &source)).unwrap();
+    let vm = Vm::default()
+        .with_memory(header.memory)
+        .with_palette(header.palette)
+        .with_canvas(header.width, header.height, header.background)
+        .quiet();
+    (CompiledProgram::new(&program), vm)
+}
+
+#[test]
+fn reset_after_finishing_runs_like_new() {
+    let (program, mut vm) = all();
+    let limits = Limits::cycles(MAX_STEPS);
+    let first = vm.run(&program, &limits).unwrap();
+    let cycles = vm.cycles();
+
+    vm.reset();
+    assert!(!vm.is_terminated());
+    assert_eq!(vm.cycles(), 0);
+    assert_eq!(vm.run(&program, &limits).unwrap(), first);
+    assert_eq!(vm.cycles(), cycles);
+}
+
+#[test]
+fn reset_part_way_runs_like_new() {
+    let (program, mut fresh) = all();
+    let limits = Limits::cycles(MAX_STEPS);
+    let expected = fresh.run(&program, &limits).unwrap();
+
+    let (_, mut vm) = all();
+    for _ in 0..program.len() / 2 {
+        vm.step(&program).unwrap();
+    }
+    vm.reset();
+    assert_eq!(vm.run(&program, &limits).unwrap(), expected);
+}
@@ -0,0 +1,85 @@
+use drawer::compile::CompiledProgram;
+use drawer::instruction::{FloatRegister::X, Instruction, Value};
+use drawer::vm::{Limits, Vm, VmError, MAX_SHAPE_POINTS};
+
+/// Run `instruction` with the pen down and return how many events the program emitted.
+fn events(instruction: Instruction) -> usize {
+    let program = [Instruction::Draw, instruction, Instruction::Halt];
+    Vm::default()
+        .run(&CompiledProgram::new(&program), &Limits::cycles(10))
+        .unwrap()
+        .len()
+}
+
+/// How many points `shape` emits, leaving out the dots drawn after every step.
+fn points(shape: Instruction) -> usize {
+    // DBG does nothing without debugging on
+    events(shape) - events(Instruction::Debug)
+}
+
+#[test]
+fn huge_spiral_stops() {
+    let shape = Instruction::Spiral(Value::Uint(1), Value::Float(1e17));
+    assert!(points(shape) <= MAX_SHAPE_POINTS);
+}
+
+#[test]
+fn infinite_spiral_draws_nothing() {
+    let shape = Instruction::Spiral(Value::Uint(1), Value::Float(f64::INFINITY));
+    assert_eq!(points(shape), 0);
+}
+
+#[test]
+fn huge_polygon_is_capped() {
+    let shape = Instruction::Poly(Value::Uint(3), Value::Float(1e12));
+    assert!(points(shape) <= MAX_SHAPE_POINTS);
+}
+
+#[test]
+fn huge_arc_is_capped() {
+    let shape = Instruction::Arc(Value::Float(1e12), Value::Uint(90));
+    assert_eq!(points(shape), MAX_SHAPE_POINTS);
+}
+
+/// Run a program that draws at `from`, then moves straight to `to`, with pixel steps on.
+fn pixel_step(from: f64, to: f64, limits: &Limits) -> Result<usize, VmError> {
+    let program = [
+        Instruction::Store(X.into(), Value::Float(from)),
+        Instruction::Draw,
+        Instruction::Forward,
+        Instruction::Store(X.into(), Value::Float(to)),
+        Instruction::Forward,
+        Instruction::Halt,
+    ];
+    Vm::default()
+        .with_pixel_steps()
+        .run(&CompiledProgram::new(&program), limits)
+        .map(|events| events.len())
+}
+
+#[test]
+fn huge_pixel_step_is_capped() {
+    // Both moves are drawn with the same number of points
+    let limits = Limits::cycles(10);
+    let events = pixel_step(0.0, 1e12, &limits).unwrap();
+    assert!(events > MAX_SHAPE_POINTS / 2);
+    assert_eq!(pixel_step(0.0, 1e13, &limits), Ok(events));
+}
+
+#[test]
+fn pixel_step_across_every_coordinate() {
+    // Too long to interpolate, so only the ends are drawn
+    assert_eq!(pixel_step(-1e300, 1e300, &Limits::cycles(10)), Ok(2));
+}
+
+#[test]
+fn pixel_step_stops_at_the_pixel_limit() {
+    let limits = Limits {
+        max_pixels: Some(100),
+        ..Limits::cycles(10)
+    };
+    assert!(matches!(
+        pixel_step(0.0, 1e12, &limits),
+        Err(VmError::PixelLimit { limit: 100, .. })
+    ));
+}
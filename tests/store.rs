@@ -0,0 +1,76 @@
+use drawer::compile::CompiledProgram;
+use drawer::instruction::{FloatRegister, Instruction, UintRegister, Value};
+use drawer::vm::{Limits, Vm};
+
+/// Run `program` followed by copying `B` into `X` and drawing, returning where it draws.
+fn drawn_x(program: &[Instruction]) -> f64 {
+    let mut program = program.to_vec();
+    program.extend_from_slice(&[
+        Instruction::Store(FloatRegister::X.into(), UintRegister::B.into()),
+        Instruction::Draw,
+        Instruction::Halt,
+    ]);
+    let events = Vm::default()
+        .run(&CompiledProgram::new(&program), &Limits::default())
+        .unwrap();
+    events.last().unwrap().x
+}
+
+/// Copy `value` into `S`, then `S` into `B`, returning what `B` ends up as.
+fn float_to_uint(value: f64) -> f64 {
+    drawn_x(&[
+        Instruction::Store(FloatRegister::S.into(), Value::Float(value)),
+        Instruction::Store(UintRegister::B.into(), FloatRegister::S.into()),
+    ])
+}
+
+#[test]
+fn float_to_uint_rounds_toward_zero() {
+    assert_eq!(float_to_uint(2.7), 2.0);
+    assert_eq!(float_to_uint(-0.9), 0.0);
+}
+
+#[test]
+fn float_to_uint_clamps() {
+    assert_eq!(float_to_uint(-3.0), 0.0);
+    assert_eq!(float_to_uint(70000.0), 65535.0);
+    assert_eq!(float_to_uint(f64::INFINITY), 65535.0);
+    assert_eq!(float_to_uint(f64::NEG_INFINITY), 0.0);
+}
+
+#[test]
+fn float_to_uint_nan_is_zero() {
+    assert_eq!(float_to_uint(f64::NAN), 0.0);
+}
+
+#[test]
+fn float_immediate_to_uint() {
+    let x = drawn_x(&[Instruction::Store(
+        UintRegister::B.into(),
+        Value::Float(9.99),
+    )]);
+    assert_eq!(x, 9.0);
+}
+
+#[test]
+fn uint_to_float_is_exact() {
+    let x = drawn_x(&[Instruction::Store(UintRegister::B.into(), 65535.into())]);
+    assert_eq!(x, 65535.0);
+}
+
+/// Convert `value` from `S` into `B` with `CVT`, returning what `B` ends up as.
+fn convert_to_uint(value: f64) -> f64 {
+    drawn_x(&[
+        Instruction::Store(FloatRegister::S.into(), Value::Float(value)),
+        Instruction::Convert(UintRegister::B.into(), FloatRegister::S.into()),
+    ])
+}
+
+#[test]
+fn convert_rounds_to_nearest() {
+    assert_eq!(convert_to_uint(2.4), 2.0);
+    assert_eq!(convert_to_uint(2.5), 3.0);
+    assert_eq!(convert_to_uint(-0.4), 0.0);
+    assert_eq!(convert_to_uint(65535.4), 65535.0);
+    assert_eq!(convert_to_uint(f64::NAN), 0.0);
+}